@@ -1,7 +1,23 @@
+use crate::modules::archive::setup_globals_archive;
 use crate::modules::basic::setup_globals_basic;
+use crate::modules::cargo::setup_globals_cargo;
+use crate::modules::cc::setup_globals_cc;
+use crate::modules::copy::setup_globals_copy;
+use crate::modules::diagnostics::setup_globals_diagnostics;
+use crate::modules::glob::setup_globals_glob;
+use crate::modules::hash::setup_globals_hash;
+use crate::modules::interrupt::{handle_interrupt, setup_globals_interrupt, wait_for_interrupt};
 use crate::modules::io::{setup_globals_io};
+use crate::modules::metadata::setup_globals_metadata;
+use crate::modules::parallel::setup_globals_parallel;
+use crate::modules::pipeline::setup_globals_pipeline;
 use crate::modules::process::setup_globals_process;
+use crate::modules::retry::{print_retry_summary, setup_globals_retry};
+use crate::modules::spawn::{setup_globals_spawn, terminate_spawned_processes};
+use crate::modules::temp::{cleanup_temp_files, setup_globals_temp};
 use crate::modules::text::setup_globals_text;
+use crate::modules::tools::setup_globals_tools;
+use crate::modules::usage::{print_usage_summary, setup_globals_usage};
 use crate::modules::utility::setup_globals_utility;
 use anyhow::Result;
 use colored::*;
@@ -39,17 +55,49 @@ impl LuaEngine {
         let script_content = fs::read_to_string(script_path).await?;
 
         setup_globals_io(self.lua.clone(), self.script_path.clone() , target, verbose)?;
+        setup_globals_glob(self.lua.clone(), self.script_path.clone(), target, verbose)?;
+        setup_globals_copy(self.lua.clone(), self.script_path.clone(), target, verbose)?;
+        setup_globals_hash(self.lua.clone(), self.script_path.clone(), target, verbose)?;
+        setup_globals_archive(self.lua.clone(), self.script_path.clone(), target, verbose)?;
+        setup_globals_metadata(self.lua.clone(), self.script_path.clone(), target, verbose)?;
+        setup_globals_temp(self.lua.clone(), self.script_path.clone(), target, verbose)?;
         setup_globals_utility(self.lua.clone(), self.script_path.clone(), target, verbose)?;
         setup_globals_basic(self.lua.clone(), self.script_path.clone(), target, verbose)?;
 
         setup_globals_text(self.lua.clone(), self.script_path.clone(), target, verbose)?;
         setup_globals_process(self.lua.clone(), self.script_path.clone(), target, verbose)?;
+        setup_globals_cargo(self.lua.clone(), self.script_path.clone(), target, verbose)?;
+        setup_globals_diagnostics(self.lua.clone(), self.script_path.clone(), target, verbose)?;
+        setup_globals_cc(self.lua.clone(), self.script_path.clone(), target, verbose)?;
+        setup_globals_parallel(self.lua.clone(), self.script_path.clone(), target, verbose)?;
+        setup_globals_pipeline(self.lua.clone(), self.script_path.clone(), target, verbose)?;
+        setup_globals_spawn(self.lua.clone(), self.script_path.clone(), target, verbose)?;
+        setup_globals_retry(self.lua.clone(), self.script_path.clone(), target, verbose)?;
+        setup_globals_tools(self.lua.clone(), self.script_path.clone(), target, verbose)?;
+        setup_globals_interrupt(self.lua.clone(), self.script_path.clone(), target, verbose)?;
+        setup_globals_usage(self.lua.clone(), self.script_path.clone(), target, verbose)?;
 
         if verbose {
             println!("{}", "Executing Lua script...".green());
         }
 
-        self.lua.load(&script_content).exec_async().await?;
+        // По Ctrl-C скрипт больше не продолжается: выполняются только on_interrupt обработчики
+        // Future скрипта живет до конца обработки, иначе kill_on_drop сразу убил бы дочерние процессы
+        let mut script = Box::pin(self.lua.load(&script_content).exec_async());
+        let result = tokio::select! {
+            result = &mut script => result.map_err(anyhow::Error::from),
+            _ = wait_for_interrupt() => Err(handle_interrupt(&self.lua, verbose).await),
+        };
+        drop(script);
+
+        // Фоновые процессы не должны переживать скрипт, даже если он завершился ошибкой
+        terminate_spawned_processes(&self.lua, verbose).await;
+        // Временные файлы удаляются после остановки процессов, которые могли их держать открытыми
+        cleanup_temp_files(&self.lua, verbose);
+        print_retry_summary(&self.lua);
+        print_usage_summary(&self.lua, verbose);
+
+        result?;
 
         Ok(())
     }
@@ -1,16 +1,40 @@
 mod lua_engine;
 
 mod modules {
+    pub mod archive;
     pub mod basic;
+    pub mod cargo;
+    pub mod cc;
+    pub mod copy;
+    pub mod diagnostics;
+    pub mod encoding;
+    pub mod glob;
+    pub mod hash;
+    pub mod interrupt;
     pub mod io;
+    pub mod jobserver;
+    pub mod metadata;
+    pub mod parallel;
+    pub mod pipeline;
     pub mod process;
+    #[cfg(unix)]
+    pub mod pty;
+    pub mod retry;
+    pub mod spawn;
+    pub mod temp;
     pub mod text;
+    pub mod tools;
+    pub mod usage;
     pub mod utility;
+    pub mod walk;
 }
 use anyhow::Result;
 use clap::Parser;
 use colored::*;
 use lua_engine::LuaEngine;
+use modules::interrupt::{install_interrupt_handler, Interrupted, INTERRUPT_EXIT_CODE};
+use modules::jobserver::init_jobserver;
+use modules::temp::init_temp;
 use std::path::PathBuf;
 use std::process::Command;
 
@@ -27,6 +51,12 @@ struct Args {
 
     #[arg(short, long, help = "Enable verbose output")]
     verbose: bool,
+
+    #[arg(short, long, help = "Maximum number of parallel jobs (default: number of CPUs)")]
+    jobs: Option<usize>,
+
+    #[arg(long, help = "Keep temporary files and directories created by temp_dir/temp_file")]
+    keep_temp: bool,
 }
 
 #[cfg(windows)]
@@ -38,6 +68,9 @@ fn setup_console() {
         .ok();
 }
 
+#[cfg(not(windows))]
+fn setup_console() {}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     setup_console();
@@ -68,10 +101,18 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
+    init_jobserver(args.jobs, args.verbose);
+    init_temp(args.keep_temp);
+    install_interrupt_handler();
+
     let mut lua_engine = LuaEngine::new();
-    lua_engine
+    let result = lua_engine
         .execute_script(&args.script, &args.target, args.verbose)
-        .await?;
+        .await;
+    if result.as_ref().is_err_and(|e| e.is::<Interrupted>()) {
+        std::process::exit(INTERRUPT_EXIT_CODE);
+    }
+    result?;
 
 
 
@@ -0,0 +1,685 @@
+use crate::modules::copy::{CopyFilter, EntryKind, collect_entries};
+use chrono::{Datelike, TimeZone, Timelike};
+use mlua::{Lua, Value};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+// Время файлов в воспроизводимых архивах без SOURCE_DATE_EPOCH: 1980-01-01 00:00:00 UTC (минимум для zip)
+const REPRODUCIBLE_MTIME: i64 = 315_532_800;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl Format {
+    fn from_name(name: &str) -> mlua::Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "zip" => Ok(Self::Zip),
+            "tar" => Ok(Self::Tar),
+            "tar.gz" | "tgz" => Ok(Self::TarGz),
+            "tar.zst" | "tzst" => Ok(Self::TarZst),
+            other => Err(mlua::Error::external(format!(
+                "Unknown archive format '{}' (expected zip, tar, tar.gz or tar.zst)",
+                other
+            ))),
+        }
+    }
+
+    // Формат по расширению файла архива
+    fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+        [
+            (".zip", Self::Zip),
+            (".tar.gz", Self::TarGz),
+            (".tgz", Self::TarGz),
+            (".tar.zst", Self::TarZst),
+            (".tzst", Self::TarZst),
+            (".tar", Self::Tar),
+        ]
+        .into_iter()
+        .find(|(extension, _)| name.ends_with(extension))
+        .map(|(_, format)| format)
+    }
+
+    // Формат из опции format или по расширению
+    fn resolve(options: Option<&mlua::Table>, path: &Path) -> mlua::Result<Self> {
+        let name = match options {
+            Some(options) => options.get::<Option<String>>("format")?,
+            None => None,
+        };
+        match name {
+            Some(name) => Self::from_name(&name),
+            None => Self::from_path(path).ok_or_else(|| {
+                mlua::Error::external(format!(
+                    "Cannot detect archive format of '{}', pass {{format = ...}}",
+                    path.display()
+                ))
+            }),
+        }
+    }
+}
+
+// Файл или папка, которые попадут в архив
+struct ArchiveEntry {
+    // Путь внутри архива с разделителями "/"
+    name: String,
+    source: PathBuf,
+    kind: EntryKind,
+}
+
+struct CreateOptions {
+    format: Format,
+    level: Option<i64>,
+    // Фиксированное время файлов и нормализованные права (None - как на диске)
+    mtime: Option<i64>,
+}
+
+pub fn setup_globals_archive(
+    lua: Lua,
+    script_path: String,
+    target: &Option<String>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let globals = lua.globals();
+
+    // ================ Архивы ================
+
+    let archive = lua.create_table()?;
+
+    // Создает архив zip, tar, tar.gz или tar.zst
+    // archive.create("dist/app.zip", "build/app")                     -- содержимое папки
+    // archive.create("dist/app-1.0.tar.gz", "build/app", {prefix = "app-1.0"})
+    // archive.create("dist/src.tar.zst", {"src", "Cargo.toml", "README.md"}, {level = 19})
+    // archive.create("dist/site.zip", "site", {exclude = "*.map", gitignore = true})
+    // Папка упаковывается без своего имени (prefix добавляет общую папку), элементы таблицы -
+    // под путями, как они указаны (относительно base, если задан)
+    // Опции: format (string) - "zip", "tar", "tar.gz", "tar.zst" (по умолчанию по расширению)
+    //        level (number) - уровень сжатия: zip и tar.gz 0-9, tar.zst 1-22
+    //        prefix (string) - папка внутри архива, в которую кладутся все файлы
+    //        base (string) - папка, относительно которой считаются пути из таблицы
+    //        reproducible (boolean, по умолчанию true) - одинаковый архив из одинаковых файлов:
+    //        время всех файлов - SOURCE_DATE_EPOCH или 1980-01-01, права 644/755, без владельцев
+    //        include, exclude, include_dirs, exclude_dirs, symlinks, gitignore - для папок, как у copy_dir
+    //        (symlinks = "copy" сохраняет ссылки в архиве)
+    // Возвращает: boolean, string? (true или false и сообщение об ошибке)
+    let create = lua.create_async_function(|_, (out, files, options): (String, Value, Option<mlua::Table>)| async move {
+        let out = PathBuf::from(out);
+        let format = Format::resolve(options.as_ref(), &out)?;
+        let (level, prefix, base, reproducible) = match &options {
+            Some(options) => (
+                options.get::<Option<i64>>("level")?,
+                options.get::<Option<String>>("prefix")?,
+                options.get::<Option<String>>("base")?,
+                options.get::<Option<bool>>("reproducible")?.unwrap_or(true),
+            ),
+            None => (None, None, None, true),
+        };
+        let sources = match files {
+            Value::String(dir) => vec![(PathBuf::from(dir.to_str()?.to_string()), None)],
+            Value::Table(files) => files
+                .sequence_values::<String>()
+                .map(|file| file.map(|file| (PathBuf::from(&file), Some(file))))
+                .collect::<mlua::Result<Vec<_>>>()?,
+            _ => return Err(mlua::Error::external("archive.create: files must be a directory path or a table of paths")),
+        };
+        let filter = Arc::new(CopyFilter::from_table(options.as_ref())?);
+
+        let prefix = prefix.map(|prefix| prefix.trim_matches('/').to_string()).filter(|prefix| !prefix.is_empty());
+        let entries = match archive_entries(sources, base.as_deref(), prefix.as_deref(), filter).await {
+            Ok(entries) => entries,
+            Err(e) => return Ok((false, Some(e.to_string()))),
+        };
+        let create_options = CreateOptions {
+            format,
+            level,
+            mtime: reproducible.then(reproducible_mtime),
+        };
+
+        let result = tokio::task::spawn_blocking(move || create_archive(&out, &entries, &create_options).map_err(|e| format!("{}: {}", out.display(), e)))
+            .await
+            .map_err(mlua::Error::external)?;
+        match result {
+            Ok(()) => Ok((true, None)),
+            Err(e) => Ok((false, Some(e))),
+        }
+    })?;
+    archive.set("create", create)?;
+
+    // Распаковывает архив zip, tar, tar.gz или tar.zst в папку
+    // archive.extract("downloads/tool.zip", "tools/tool")
+    // archive.extract("downloads/sdk.tgz", "vendor", {format = "tar.gz"})
+    // Элементы с абсолютными путями или "..", а также ссылки, ведущие за пределы dest,
+    // считаются ошибкой: ничего не записывается за пределами папки назначения
+    // Опции: format (string) - формат, если его нельзя определить по расширению
+    // Возвращает: boolean, string? (true или false и сообщение об ошибке)
+    let extract = lua.create_async_function(|_, (file, dest, options): (String, String, Option<mlua::Table>)| async move {
+        let file = PathBuf::from(file);
+        let format = Format::resolve(options.as_ref(), &file)?;
+        let dest = PathBuf::from(dest);
+        let result = tokio::task::spawn_blocking(move || extract_archive(&file, &dest, format).map_err(|e| format!("{}: {}", file.display(), e)))
+            .await
+            .map_err(mlua::Error::external)?;
+        match result {
+            Ok(()) => Ok((true, None)),
+            Err(e) => Ok((false, Some(e))),
+        }
+    })?;
+    archive.set("extract", extract)?;
+
+    globals.set("archive", archive)?;
+
+    Ok(())
+}
+
+// ================ Вспомогательные функции ================
+
+// Время файлов воспроизводимого архива: SOURCE_DATE_EPOCH (соглашение reproducible-builds.org) или 1980-01-01
+fn reproducible_mtime() -> i64 {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.trim().parse::<i64>().ok())
+        .map(|epoch| epoch.max(REPRODUCIBLE_MTIME))
+        .unwrap_or(REPRODUCIBLE_MTIME)
+}
+
+// Собирает элементы архива, отсортированные по имени
+async fn archive_entries(
+    sources: Vec<(PathBuf, Option<String>)>,
+    base: Option<&str>,
+    prefix: Option<&str>,
+    filter: Arc<CopyFilter>,
+) -> io::Result<Vec<ArchiveEntry>> {
+    let mut entries = Vec::new();
+    for (source, given) in sources {
+        let metadata = fs::metadata(&source).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", source.display(), e)))?;
+        // Папка, переданная строкой, упаковывается без своего имени
+        let name = given.as_ref().map(|given| entry_name(Path::new(given), base));
+        let join = |relative: &str| {
+            [prefix, name.as_deref(), Some(relative)]
+                .into_iter()
+                .flatten()
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join("/")
+        };
+
+        if !metadata.is_dir() {
+            // Файл, переданный строкой, кладется под своим именем
+            let file_name = source.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            entries.push(ArchiveEntry {
+                name: join(if name.is_some() { "" } else { &file_name }),
+                source,
+                kind: EntryKind::File,
+            });
+            continue;
+        }
+        if name.is_some() {
+            entries.push(ArchiveEntry {
+                name: join(""),
+                source: source.clone(),
+                kind: EntryKind::Dir,
+            });
+        }
+        let collected = collect_entries(&source, filter.clone())
+            .await
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", source.display(), e)))?;
+        entries.extend(collected.into_iter().map(|entry| ArchiveEntry {
+            name: join(&entry.relative),
+            source: entry.source,
+            kind: entry.kind,
+        }));
+    }
+    if let Some(prefix) = prefix {
+        entries.push(ArchiveEntry {
+            name: prefix.to_string(),
+            source: PathBuf::new(),
+            kind: EntryKind::Dir,
+        });
+    }
+
+    entries.retain(|entry| !entry.name.is_empty());
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    entries.dedup_by(|a, b| a.name == b.name);
+    Ok(entries)
+}
+
+// Имя элемента из таблицы: путь относительно base, как указан, или имя файла для путей вне текущей папки
+fn entry_name(path: &Path, base: Option<&str>) -> String {
+    let path = match base {
+        Some(base) => path.strip_prefix(base).unwrap_or(path),
+        None => path,
+    };
+    let outside = path.components().any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+    let path = if outside {
+        Path::new(path.file_name().unwrap_or(path.as_os_str()))
+    } else {
+        path
+    };
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn create_archive(out: &Path, entries: &[ArchiveEntry], options: &CreateOptions) -> io::Result<()> {
+    if let Some(parent) = out.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let file = File::create(out)?;
+    let level = options.level;
+    match options.format {
+        Format::Zip => write_zip(file, entries, options),
+        Format::Tar => write_tar(file, entries, options).map(drop),
+        Format::TarGz => {
+            let level = level.map(|level| level.clamp(0, 9) as u32).unwrap_or(6);
+            // Заголовок gzip без имени файла и времени, поэтому архив воспроизводим
+            let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::new(level));
+            write_tar(encoder, entries, options)?.finish().map(drop)
+        }
+        Format::TarZst => {
+            let level = level.map(|level| level.clamp(1, 22) as i32).unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL);
+            let encoder = zstd::Encoder::new(file, level)?;
+            write_tar(encoder, entries, options)?.finish().map(drop)
+        }
+    }
+}
+
+// Права файла в архиве: как на диске или нормализованные 644/755
+fn entry_mode(metadata: Option<&fs::Metadata>, kind: EntryKind, reproducible: bool) -> u32 {
+    #[cfg(unix)]
+    let mode = {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.map(|metadata| metadata.permissions().mode() & 0o7777)
+    };
+    #[cfg(not(unix))]
+    let mode = metadata.map(|metadata| if metadata.permissions().readonly() { 0o444 } else { 0o644 });
+
+    match (kind, mode) {
+        (EntryKind::Dir, _) => 0o755,
+        (_, Some(mode)) if reproducible => {
+            if mode & 0o111 != 0 {
+                0o755
+            } else {
+                0o644
+            }
+        }
+        (_, Some(mode)) => mode,
+        (_, None) => 0o644,
+    }
+}
+
+fn modified_seconds(metadata: Option<&fs::Metadata>) -> i64 {
+    metadata
+        .and_then(|metadata| metadata.modified().ok())
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(REPRODUCIBLE_MTIME)
+}
+
+fn write_tar<W: Write>(writer: W, entries: &[ArchiveEntry], options: &CreateOptions) -> io::Result<W> {
+    let mut builder = tar::Builder::new(writer);
+    for entry in entries {
+        let metadata = match entry.kind {
+            EntryKind::Symlink => fs::symlink_metadata(&entry.source).ok(),
+            _ => fs::metadata(&entry.source).ok(),
+        };
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(entry_mode(metadata.as_ref(), entry.kind, options.mtime.is_some()));
+        header.set_mtime(options.mtime.unwrap_or_else(|| modified_seconds(metadata.as_ref())).max(0) as u64);
+        header.set_uid(0);
+        header.set_gid(0);
+        match entry.kind {
+            EntryKind::Dir => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_size(0);
+                builder.append_data(&mut header, format!("{}/", entry.name), io::empty())?;
+            }
+            EntryKind::File => {
+                let file = File::open(&entry.source)?;
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_size(file.metadata()?.len());
+                builder.append_data(&mut header, &entry.name, file)?;
+            }
+            EntryKind::Symlink => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                builder.append_link(&mut header, &entry.name, fs::read_link(&entry.source)?)?;
+            }
+        }
+    }
+    builder.into_inner()
+}
+
+fn write_zip(file: File, entries: &[ArchiveEntry], options: &CreateOptions) -> io::Result<()> {
+    use zip::write::SimpleFileOptions;
+
+    let mut writer = zip::ZipWriter::new(file);
+    for entry in entries {
+        let metadata = match entry.kind {
+            EntryKind::Symlink => fs::symlink_metadata(&entry.source).ok(),
+            _ => fs::metadata(&entry.source).ok(),
+        };
+        let mtime = options.mtime.unwrap_or_else(|| modified_seconds(metadata.as_ref()));
+        let file_options = SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .compression_level(options.level.map(|level| level.clamp(0, 9)))
+            .last_modified_time(zip_time(mtime, options.mtime.is_some()))
+            .unix_permissions(entry_mode(metadata.as_ref(), entry.kind, options.mtime.is_some()))
+            .large_file(metadata.as_ref().is_some_and(|metadata| metadata.len() >= u32::MAX as u64));
+        match entry.kind {
+            EntryKind::Dir => writer.add_directory(format!("{}/", entry.name), file_options)?,
+            EntryKind::File => {
+                writer.start_file(entry.name.as_str(), file_options)?;
+                io::copy(&mut File::open(&entry.source)?, &mut writer)?;
+            }
+            EntryKind::Symlink => {
+                let link = fs::read_link(&entry.source)?.to_string_lossy().replace('\\', "/");
+                writer.add_symlink(entry.name.as_str(), link, file_options)?;
+            }
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+// Время zip хранится без часового пояса: обычные архивы - в местном времени (как у утилиты zip),
+// воспроизводимые - в UTC, чтобы не зависеть от машины
+fn zip_time(seconds: i64, reproducible: bool) -> zip::DateTime {
+    let time = if reproducible {
+        chrono::Utc.timestamp_opt(seconds, 0).single().map(|time| time.naive_utc())
+    } else {
+        chrono::Local.timestamp_opt(seconds, 0).single().map(|time| time.naive_local())
+    };
+    time.and_then(|time| {
+        zip::DateTime::from_date_and_time(
+            u16::try_from(time.year()).ok()?,
+            time.month() as u8,
+            time.day() as u8,
+            time.hour() as u8,
+            time.minute() as u8,
+            time.second() as u8,
+        )
+        .ok()
+    })
+    .unwrap_or_default()
+}
+
+fn extract_archive(file: &Path, dest: &Path, format: Format) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+    let archive = File::open(file)?;
+    match format {
+        Format::Zip => extract_zip(archive, dest),
+        Format::Tar => extract_tar(archive, dest),
+        Format::TarGz => extract_tar(flate2::read::GzDecoder::new(archive), dest),
+        Format::TarZst => extract_tar(zstd::Decoder::new(archive)?, dest),
+    }
+}
+
+fn extract_tar<R: Read>(reader: R, dest: &Path) -> io::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_mtime(true);
+    let canonical_dest = fs::canonicalize(dest)?;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let relative = safe_path(&path)?;
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let link = entry
+                .link_name()?
+                .ok_or_else(|| invalid_entry(&path, "link without target"))?
+                .into_owned();
+            if entry_type.is_symlink() {
+                // Как в zip: глубина ссылки считается от настоящей папки с учетом уже
+                // распакованных ссылок ("d" -> "." и "d/e/l" -> "../../x")
+                let parent = match relative.parent() {
+                    Some(parent) => create_dirs_inside(&canonical_dest, parent)?,
+                    None => canonical_dest.clone(),
+                };
+                let file_name = relative.file_name().ok_or_else(|| invalid_entry(&relative, "empty path"))?;
+                let target = parent.join(file_name);
+                check_symlink(target.strip_prefix(&canonical_dest).unwrap_or(&relative), &link)?;
+            } else {
+                safe_path(&link)?;
+            }
+        }
+        // unpack_in дополнительно проверяет, что родительские папки не ведут за пределы dest
+        if !entry.unpack_in(dest)? {
+            return Err(invalid_entry(&path, "path outside of the destination"));
+        }
+    }
+    Ok(())
+}
+
+fn extract_zip(file: File, dest: &Path) -> io::Result<()> {
+    let mut archive = zip::ZipArchive::new(file)?;
+    let canonical_dest = fs::canonicalize(dest)?;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let name = PathBuf::from(entry.name());
+        let relative = entry.enclosed_name().ok_or_else(|| invalid_entry(&name, "path outside of the destination"))?;
+        let relative = safe_path(&relative)?;
+
+        if entry.is_dir() {
+            create_dirs_inside(&canonical_dest, &relative)?;
+            continue;
+        }
+        // Папка элемента с учетом уже распакованных ссылок: "d" -> "." и "d/e/l" -> "../../x"
+        // проходят проверку check_symlink по отдельности, но вместе ведут за пределы dest
+        let parent = match relative.parent() {
+            Some(parent) => create_dirs_inside(&canonical_dest, parent)?,
+            None => canonical_dest.clone(),
+        };
+        let file_name = relative.file_name().ok_or_else(|| invalid_entry(&relative, "empty path"))?;
+        let target = parent.join(file_name);
+        if entry.is_symlink() {
+            let mut link = String::new();
+            entry.read_to_string(&mut link)?;
+            // Глубина ссылки считается от настоящей папки, а не от пути в архиве
+            let real_relative = target.strip_prefix(&canonical_dest).unwrap_or(&relative);
+            check_symlink(real_relative, Path::new(&link))?;
+            if fs::symlink_metadata(&target).is_ok() {
+                fs::remove_file(&target)?;
+            }
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&link, &target)?;
+            #[cfg(windows)]
+            std::os::windows::fs::symlink_file(&link, &target)?;
+            continue;
+        }
+
+        // Существующая ссылка на месте файла заменяется, а не перезаписывается насквозь
+        if fs::symlink_metadata(&target).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            fs::remove_file(&target)?;
+        }
+        let mut output = File::create(&target)?;
+        io::copy(&mut entry, &mut output)?;
+        #[cfg(unix)]
+        if let Some(mode) = entry.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&target, fs::Permissions::from_mode(mode & 0o777))?;
+        }
+        if let Some(modified) = entry.last_modified().and_then(local_time) {
+            let _ = output.set_modified(modified);
+        }
+    }
+    Ok(())
+}
+
+// Создает папки relative внутри dest по одной, проверяя после каждой, что ее настоящий путь
+// (с разрешенными ссылками) остается внутри dest; папки за пределами dest не создаются
+// Возвращает: канонический путь последней папки
+fn create_dirs_inside(canonical_dest: &Path, relative: &Path) -> io::Result<PathBuf> {
+    let mut current = canonical_dest.to_path_buf();
+    for component in relative.components() {
+        let next = current.join(component);
+        match fs::create_dir(&next) {
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+            _ => {}
+        }
+        current = fs::canonicalize(&next)?;
+        if !current.starts_with(canonical_dest) {
+            return Err(invalid_entry(relative, "path outside of the destination"));
+        }
+    }
+    Ok(current)
+}
+
+fn local_time(time: zip::DateTime) -> Option<std::time::SystemTime> {
+    let time = chrono::Local
+        .with_ymd_and_hms(
+            time.year() as i32,
+            time.month() as u32,
+            time.day() as u32,
+            time.hour() as u32,
+            time.minute() as u32,
+            time.second() as u32,
+        )
+        .earliest()?;
+    Some(time.into())
+}
+
+// Путь элемента архива без "..", корня и префиксов диска
+fn safe_path(path: &Path) -> io::Result<PathBuf> {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => result.push(part),
+            Component::CurDir => {}
+            _ => return Err(invalid_entry(path, "path outside of the destination")),
+        }
+    }
+    if result.as_os_str().is_empty() {
+        return Err(invalid_entry(path, "empty path"));
+    }
+    Ok(result)
+}
+
+// Символическая ссылка должна указывать внутрь папки назначения
+fn check_symlink(relative: &Path, link: &Path) -> io::Result<()> {
+    if link.is_absolute() || link.has_root() {
+        return Err(invalid_entry(relative, "symlink points outside of the destination"));
+    }
+    let mut depth = relative.components().count() as i64 - 1;
+    for component in link.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::ParentDir => depth -= 1,
+            Component::CurDir => {}
+            _ => return Err(invalid_entry(relative, "symlink points outside of the destination")),
+        }
+        if depth < 0 {
+            return Err(invalid_entry(relative, "symlink points outside of the destination"));
+        }
+    }
+    Ok(())
+}
+
+fn invalid_entry(path: &Path, reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("unsafe archive entry '{}': {}", path.display(), reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Пустая папка для теста внутри системной папки временных файлов
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("solver-archive-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::canonicalize(dir).unwrap()
+    }
+
+    #[test]
+    fn safe_path_strips_current_dir_components() {
+        assert_eq!(safe_path(Path::new("./a/./b.txt")).unwrap(), PathBuf::from("a/b.txt"));
+        assert_eq!(safe_path(Path::new("dir/")).unwrap(), PathBuf::from("dir"));
+    }
+
+    #[test]
+    fn safe_path_rejects_escaping_paths() {
+        assert!(safe_path(Path::new("../evil")).is_err());
+        assert!(safe_path(Path::new("a/../../evil")).is_err());
+        assert!(safe_path(Path::new("/etc/passwd")).is_err());
+        assert!(safe_path(Path::new("")).is_err());
+        assert!(safe_path(Path::new("./")).is_err());
+    }
+
+    #[test]
+    fn check_symlink_allows_links_inside_destination() {
+        assert!(check_symlink(Path::new("lib/libfoo.so"), Path::new("libfoo.so.1")).is_ok());
+        assert!(check_symlink(Path::new("a/b/link"), Path::new("../../c")).is_ok());
+        assert!(check_symlink(Path::new("a/link"), Path::new("./b/../c")).is_ok());
+    }
+
+    #[test]
+    fn check_symlink_rejects_links_outside_destination() {
+        assert!(check_symlink(Path::new("link"), Path::new("../x")).is_err());
+        assert!(check_symlink(Path::new("a/b/link"), Path::new("../../../x")).is_err());
+        // Промежуточный выход за пределы не допускается, даже если путь потом возвращается
+        assert!(check_symlink(Path::new("a/link"), Path::new("../../dest/x")).is_err());
+        assert!(check_symlink(Path::new("link"), Path::new("/etc/passwd")).is_err());
+    }
+
+    #[test]
+    fn create_dirs_inside_creates_nested_directories() {
+        let dest = test_dir("nested");
+        let created = create_dirs_inside(&dest, Path::new("a/b/c")).unwrap();
+        assert_eq!(created, dest.join("a/b/c"));
+        assert!(created.is_dir());
+        fs::remove_dir_all(&dest).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn create_dirs_inside_rejects_symlink_chains() {
+        let root = test_dir("chain");
+        let dest = root.join("dest");
+        fs::create_dir_all(dest.join("e")).unwrap();
+        std::os::unix::fs::symlink(".", dest.join("d")).unwrap();
+        std::os::unix::fs::symlink("../../x", dest.join("e/l")).unwrap();
+        fs::create_dir(root.join("x")).unwrap();
+
+        // "d/e" ведет в dest/e - это внутри
+        assert_eq!(create_dirs_inside(&dest, Path::new("d/e")).unwrap(), dest.join("e"));
+        // "d/e/l" через две ссылки ведет в root/x
+        assert!(create_dirs_inside(&dest, Path::new("d/e/l")).is_err());
+        // За пределами dest ничего не создается
+        assert!(create_dirs_inside(&dest, Path::new("d/e/l/sub")).is_err());
+        assert!(!root.join("x/sub").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_tar_rejects_symlink_chains() {
+        let root = test_dir("tar-chain");
+        let dest = root.join("dest");
+        fs::create_dir_all(&dest).unwrap();
+        fs::create_dir(root.join("x")).unwrap();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, link) in [("d", "."), ("d/e/l", "../../x")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            header.set_mode(0o777);
+            builder.append_link(&mut header, path, link).unwrap();
+        }
+        let data = builder.into_inner().unwrap();
+
+        assert!(extract_tar(data.as_slice(), &dest).is_err());
+        assert!(fs::symlink_metadata(dest.join("e/l")).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
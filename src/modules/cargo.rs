@@ -0,0 +1,321 @@
+use crate::modules::jobserver::set_jobserver_env;
+use crate::modules::process::{call_output_callback, output_line, spawn_command_with_line_output, TaskOptions};
+use crate::modules::text::strip_ansi_bytes;
+use crate::modules::usage::{record_usage, wait_with_usage};
+use crate::modules::utility::string_list;
+use mlua::{Lua, Value};
+use serde_json::Value as JsonValue;
+use std::time::Instant;
+use tokio::process::Command;
+
+// Подкоманды cargo, доступные через таблицу cargo
+const CARGO_COMMANDS: &[&str] = &["build", "test", "check", "clippy", "run", "doc"];
+
+pub fn setup_globals_cargo(
+    lua: Lua,
+    script_path: String,
+    target: &Option<String>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let globals = lua.globals();
+
+    // ================ Cargo команды ================
+
+    // Таблица cargo с подкомандами build, test, check, clippy, run, doc
+    // local result = cargo.build({release = true})
+    // local result = cargo.test({package = "core", features = {"serde", "async"}})
+    // local result = cargo.clippy({workspace = true, trailing_args = {"-D", "warnings"}})
+    // local result = cargo.run({package = "cli", trailing_args = {"--help"}, quiet = true})
+    // local result = cargo.build({target = "x86_64-pc-windows-gnu", profile = "dist",
+    //     callback = function(line, stream) println(line) end})
+    // Опции: release (boolean), profile (string), package (string | table), workspace (boolean),
+    //        features (string | table), all_features (boolean), no_default_features (boolean),
+    //        target (string, target triple), args (table, дополнительные аргументы cargo),
+    //        trailing_args (table, аргументы после "--": для программы, тестов или линтера),
+    //        cwd (string), env (table),
+    //        callback (function(line, stream)) - обработка вывода, как у task_run; без callback
+    //        вывод печатается в консоль, quiet = true отключает печать
+    //        pty, strip_ansi, encoding - как у task_run
+    //        json (boolean, по умолчанию true) - запуск с --message-format=json: сообщения компилятора
+    //        разбираются в diagnostics, а в консоль и callback попадает их обычный текст
+    // Возвращает: таблица {success, exit_code, command, stdout, stderr, duration, usage, aborted, abort_reason,
+    //             diagnostics = {{level, code, message, file, line, column, end_line, end_column,
+    //                             rendered, package, notes}, ...},
+    //             errors, warnings, artifacts = {{package, target, kind, filenames, executable, fresh}, ...},
+    //             executables = {path, ...}}
+    // Пути file в diagnostics указаны относительно корня workspace
+    let cargo = lua.create_table()?;
+    for &subcommand in CARGO_COMMANDS {
+        let function = lua.create_async_function(move |lua, options: Option<mlua::Table>| async move {
+            run_cargo(&lua, subcommand, options).await
+        })?;
+        cargo.set(subcommand, function)?;
+    }
+    globals.set("cargo", cargo)?;
+
+    // Выполняет cargo build с опциональным release режимом (сокращение для cargo.build)
+    // local result = cargo_build(true) -- release build
+    // Возвращает: таблица, как у cargo.build
+    let cargo_build = lua.create_async_function(|lua, release_mode: Option<bool>| async move {
+        let options = lua.create_table()?;
+        options.set("release", release_mode.unwrap_or(false))?;
+        run_cargo(&lua, "build", Some(options)).await
+    })?;
+    globals.set("cargo_build", cargo_build)?;
+
+    Ok(())
+}
+
+// ================ Вспомогательные функции ================
+
+async fn run_cargo(lua: &Lua, subcommand: &str, options: Option<mlua::Table>) -> mlua::Result<mlua::Table> {
+    let task_options = TaskOptions::from_table(options.as_ref())?;
+    let json = match &options {
+        Some(options) => options.get::<Option<bool>>("json")?.unwrap_or(true),
+        None => true,
+    };
+    let mut args = cargo_args(subcommand, options.as_ref())?;
+    if json {
+        // В псевдотерминале сохраняем цвета в тексте сообщений
+        let format = if task_options.pty { "json-diagnostic-rendered-ansi" } else { "json" };
+        let position = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+        args.insert(position, format!("--message-format={}", format));
+    }
+
+    let program = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let mut command = Command::new(&program);
+    command.args(&args).kill_on_drop(true);
+    set_jobserver_env(&mut command, true);
+
+    let mut callback = None;
+    let mut quiet = false;
+    if let Some(options) = &options {
+        if let Some(cwd) = options.get::<Option<String>>("cwd")? {
+            command.current_dir(cwd);
+        }
+        if let Some(env) = options.get::<Option<mlua::Table>>("env")? {
+            for pair in env.pairs::<String, String>() {
+                let (key, value) = pair?;
+                command.env(key, value);
+            }
+        }
+        callback = options.get::<Option<mlua::Function>>("callback")?;
+        quiet = options.get::<Option<bool>>("quiet")?.unwrap_or(false);
+    }
+
+    let command_line = format!("cargo {}", args.join(" "));
+    let start_time = Instant::now();
+    let (mut process, mut lines) = spawn_command_with_line_output(command, task_options.pty, task_options.encoding)
+        .map_err(|e| mlua::Error::external(format!("Failed to run {}: {}", command_line, e)))?;
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let mut messages = CargoMessages::default();
+    let mut abort_reason = None;
+
+    'output: while let Some((line, stream)) = lines.recv().await {
+        // JSON сообщение заменяется его текстовым представлением, остальные строки идут как есть
+        let display = match json.then(|| parse_message(&line)).flatten() {
+            Some(message) => messages.add(lua, message)?,
+            None => vec![(line, stream)],
+        };
+
+        for (line, stream) in display {
+            let text = output_line(lua, &line, task_options.strip_ansi)?;
+            let collected = if stream == "stderr" { &mut stderr } else { &mut stdout };
+            if !collected.is_empty() {
+                collected.push(b'\n');
+            }
+            collected.extend_from_slice(&text.as_bytes());
+
+            match &callback {
+                Some(callback) => {
+                    let line = text.to_string_lossy();
+                    abort_reason = call_output_callback(callback, (text, stream), &line, "Callback error").await?;
+                    if abort_reason.is_some() {
+                        let _ = process.start_kill();
+                        break 'output;
+                    }
+                }
+                None if quiet => {}
+                None if stream == "stderr" => eprintln!("{}", text.to_string_lossy()),
+                None => println!("{}", text.to_string_lossy()),
+            }
+        }
+    }
+
+    let (status, usage) = wait_with_usage(&mut process, start_time).await
+        .map_err(|e| mlua::Error::external(format!("Process wait error: {}", e)))?;
+    record_usage(lua, &command_line, usage);
+
+    let result = lua.create_table()?;
+    result.set("success", status.success() && abort_reason.is_none())?;
+    result.set("exit_code", status.code().unwrap_or(-1))?;
+    result.set("command", command_line)?;
+    result.set("stdout", lua.create_string(&stdout)?)?;
+    result.set("stderr", lua.create_string(&stderr)?)?;
+    result.set("duration", start_time.elapsed().as_secs_f64())?;
+    result.set("usage", usage.to_table(lua)?)?;
+    result.set("aborted", abort_reason.is_some())?;
+    result.set("abort_reason", abort_reason)?;
+    result.set("diagnostics", messages.diagnostics)?;
+    result.set("errors", messages.errors)?;
+    result.set("warnings", messages.warnings)?;
+    result.set("artifacts", messages.artifacts)?;
+    result.set("executables", messages.executables)?;
+    Ok(result)
+}
+
+// Сообщения cargo --message-format=json, собранные за время выполнения
+#[derive(Default)]
+struct CargoMessages {
+    diagnostics: Vec<mlua::Table>,
+    errors: u32,
+    warnings: u32,
+    artifacts: Vec<mlua::Table>,
+    executables: Vec<String>,
+}
+
+impl CargoMessages {
+    // Запоминает сообщение и возвращает строки, которые нужно показать вместо JSON
+    fn add(&mut self, lua: &Lua, message: JsonValue) -> mlua::Result<Vec<(Vec<u8>, &'static str)>> {
+        let package = message["package_id"].as_str().map(package_name);
+        match message["reason"].as_str() {
+            Some("compiler-message") => {
+                let diagnostic = &message["message"];
+                let level = diagnostic["level"].as_str().unwrap_or("");
+                match level {
+                    "error" | "error: internal compiler error" => self.errors += 1,
+                    "warning" => self.warnings += 1,
+                    _ => {}
+                }
+                let rendered = diagnostic["rendered"].as_str().unwrap_or("").trim_end().to_string();
+                self.diagnostics.push(diagnostic_table(lua, diagnostic, package)?);
+                Ok(rendered.lines().map(|line| (line.as_bytes().to_vec(), "stderr")).collect())
+            }
+            Some("compiler-artifact") => {
+                let artifact = lua.create_table()?;
+                artifact.set("package", package)?;
+                artifact.set("target", message["target"]["name"].as_str())?;
+                artifact.set("kind", json_strings(&message["target"]["kind"]))?;
+                artifact.set("filenames", json_strings(&message["filenames"]))?;
+                let executable = message["executable"].as_str().map(str::to_string);
+                artifact.set("executable", executable.clone())?;
+                artifact.set("fresh", message["fresh"].as_bool().unwrap_or(false))?;
+                self.artifacts.push(artifact);
+                self.executables.extend(executable);
+                Ok(Vec::new())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+// Строка stdout считается сообщением cargo, только если это JSON объект с известным полем reason
+// (вывод самой программы в cargo run/test проходит как есть)
+// В псевдотерминале перед JSON могут оказаться escape-последовательности прогресс-бара cargo
+// (внутри JSON символ ESC всегда экранирован, поэтому удалять их безопасно)
+fn parse_message(line: &[u8]) -> Option<JsonValue> {
+    let line = strip_ansi_bytes(line);
+    if line.first() != Some(&b'{') {
+        return None;
+    }
+    let message: JsonValue = serde_json::from_slice(&line).ok()?;
+    match message.get("reason")?.as_str()? {
+        "compiler-message" | "compiler-artifact" | "build-script-executed" | "build-finished" => Some(message),
+        _ => None,
+    }
+}
+
+fn diagnostic_table(lua: &Lua, diagnostic: &JsonValue, package: Option<String>) -> mlua::Result<mlua::Table> {
+    let table = lua.create_table()?;
+    table.set("level", diagnostic["level"].as_str())?;
+    table.set("code", diagnostic["code"]["code"].as_str())?;
+    table.set("message", diagnostic["message"].as_str())?;
+    table.set("rendered", diagnostic["rendered"].as_str())?;
+    table.set("package", package)?;
+
+    let spans = diagnostic["spans"].as_array().map(Vec::as_slice).unwrap_or_default();
+    if let Some(span) = spans.iter().find(|span| span["is_primary"].as_bool() == Some(true)).or(spans.first()) {
+        table.set("file", span["file_name"].as_str())?;
+        table.set("line", span["line_start"].as_u64())?;
+        table.set("column", span["column_start"].as_u64())?;
+        table.set("end_line", span["line_end"].as_u64())?;
+        table.set("end_column", span["column_end"].as_u64())?;
+    }
+
+    // Дочерние сообщения: note, help
+    let notes = lua.create_table()?;
+    for child in diagnostic["children"].as_array().map(Vec::as_slice).unwrap_or_default() {
+        let note = lua.create_table()?;
+        note.set("level", child["level"].as_str())?;
+        note.set("message", child["message"].as_str())?;
+        notes.push(note)?;
+    }
+    table.set("notes", notes)?;
+    Ok(table)
+}
+
+// Имя пакета из package_id: "path+file:///work/core#0.1.0", "registry+https://...#serde@1.0.0"
+// или старый формат "serde 1.0.0 (registry+https://...)"
+fn package_name(package_id: &str) -> String {
+    if let Some((name, _)) = package_id.split_once(' ') {
+        return name.to_string();
+    }
+    let (url, fragment) = package_id.split_once('#').unwrap_or((package_id, ""));
+    if let Some((name, _)) = fragment.split_once('@') {
+        return name.to_string();
+    }
+    url.trim_end_matches('/').rsplit('/').next().unwrap_or(url).to_string()
+}
+
+fn json_strings(value: &JsonValue) -> Vec<String> {
+    value
+        .as_array()
+        .map(|items| items.iter().filter_map(|item| item.as_str().map(str::to_string)).collect())
+        .unwrap_or_default()
+}
+
+// Собирает аргументы командной строки cargo из опций
+fn cargo_args(subcommand: &str, options: Option<&mlua::Table>) -> mlua::Result<Vec<String>> {
+    let mut args = vec![subcommand.to_string()];
+    let Some(options) = options else {
+        return Ok(args);
+    };
+
+    if options.get::<Option<bool>>("release")?.unwrap_or(false) {
+        args.push("--release".to_string());
+    }
+    if let Some(profile) = options.get::<Option<String>>("profile")? {
+        args.extend(["--profile".to_string(), profile]);
+    }
+    if options.get::<Option<bool>>("workspace")?.unwrap_or(false) {
+        args.push("--workspace".to_string());
+    }
+    for package in string_list(options.get("package")?, "package")? {
+        args.extend(["--package".to_string(), package]);
+    }
+
+    let features = string_list(options.get("features")?, "features")?;
+    if !features.is_empty() {
+        args.extend(["--features".to_string(), features.join(",")]);
+    }
+    if options.get::<Option<bool>>("all_features")?.unwrap_or(false) {
+        args.push("--all-features".to_string());
+    }
+    if options.get::<Option<bool>>("no_default_features")?.unwrap_or(false) {
+        args.push("--no-default-features".to_string());
+    }
+    if let Some(target) = options.get::<Option<String>>("target")? {
+        args.extend(["--target".to_string(), target]);
+    }
+
+    args.extend(string_list(options.get("args")?, "args")?);
+
+    let trailing = string_list(options.get("trailing_args")?, "trailing_args")?;
+    if !trailing.is_empty() {
+        args.push("--".to_string());
+        args.extend(trailing);
+    }
+    Ok(args)
+}
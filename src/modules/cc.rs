@@ -0,0 +1,497 @@
+use crate::modules::diagnostics::diagnostics_summary;
+use crate::modules::jobserver::jobserver;
+use crate::modules::process::command_output;
+use crate::modules::usage::{record_usage, ProcessUsage};
+use crate::modules::utility::string_list;
+use colored::Colorize;
+use futures::stream::{self, StreamExt};
+use mlua::{Lua, Value};
+use serde_json::{json, Value as JsonValue};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+use tokio::process::Command;
+
+// Расширения исходников C++ (остальные компилируются как C)
+const CXX_EXTENSIONS: &[&str] = &["cpp", "cc", "cxx", "c++", "C", "mm"];
+
+// Что собирается из объектных файлов
+#[derive(Clone, Copy, PartialEq)]
+enum ArtifactKind {
+    Executable,
+    StaticLibrary,
+}
+
+// Описание цели сборки из Lua таблицы
+struct CcTarget {
+    name: String,
+    sources: Vec<String>,
+    compiler: String,
+    compile_flags: Vec<String>,
+    link_flags: Vec<String>,
+    build_dir: PathBuf,
+    jobs: usize,
+    compile_commands: Option<PathBuf>,
+    quiet: bool,
+}
+
+impl CcTarget {
+    fn from_table(spec: &mlua::Table) -> mlua::Result<Self> {
+        let name = spec
+            .get::<Option<String>>("name")?
+            .ok_or_else(|| mlua::Error::external("cc: 'name' is required"))?;
+        let sources = string_list(spec.get("sources")?, "sources")?;
+        if sources.is_empty() {
+            return Err(mlua::Error::external(format!("cc: target '{}' has no sources", name)));
+        }
+
+        let is_cxx = sources.iter().any(|source| {
+            Path::new(source)
+                .extension()
+                .is_some_and(|ext| CXX_EXTENSIONS.contains(&ext.to_string_lossy().as_ref()))
+        });
+        let compiler = match spec.get::<Option<String>>("compiler")? {
+            Some(compiler) => compiler,
+            None if is_cxx => std::env::var("CXX").unwrap_or_else(|_| "c++".to_string()),
+            None => std::env::var("CC").unwrap_or_else(|_| "cc".to_string()),
+        };
+
+        let mut compile_flags = Vec::new();
+        for dir in string_list(spec.get("include_dirs")?, "include_dirs")? {
+            compile_flags.push(format!("-I{}", dir));
+        }
+        compile_flags.extend(defines(spec.get("defines")?)?);
+        compile_flags.extend(string_list(spec.get("flags")?, "flags")?);
+
+        let mut link_flags = string_list(spec.get("link_flags")?, "link_flags")?;
+        for dir in string_list(spec.get("lib_dirs")?, "lib_dirs")? {
+            link_flags.push(format!("-L{}", dir));
+        }
+        for lib in string_list(spec.get("libs")?, "libs")? {
+            link_flags.push(format!("-l{}", lib));
+        }
+
+        let build_dir = PathBuf::from(spec.get::<Option<String>>("build_dir")?.unwrap_or_else(|| "build".to_string()));
+        let compile_commands = match spec.get::<Value>("compile_commands")? {
+            Value::Boolean(true) => Some(build_dir.join("compile_commands.json")),
+            Value::String(path) => Some(PathBuf::from(path.to_str()?.to_string())),
+            _ => None,
+        };
+
+        Ok(Self {
+            name,
+            sources,
+            compiler,
+            compile_flags,
+            link_flags,
+            jobs: spec
+                .get::<Option<usize>>("jobs")?
+                .unwrap_or_else(|| jobserver().limit())
+                .max(1),
+            compile_commands,
+            quiet: spec.get::<Option<bool>>("quiet")?.unwrap_or(false),
+            build_dir,
+        })
+    }
+
+    fn output_path(&self, kind: ArtifactKind) -> PathBuf {
+        match kind {
+            ArtifactKind::Executable if cfg!(windows) => self.build_dir.join(format!("{}.exe", self.name)),
+            ArtifactKind::Executable => self.build_dir.join(&self.name),
+            ArtifactKind::StaticLibrary => self.build_dir.join(format!("lib{}.a", self.name)),
+        }
+    }
+
+    // Аргументы компиляции одного файла (без имени компилятора)
+    fn compile_args(&self, source: &str, object: &Path) -> Vec<String> {
+        let mut args = self.compile_flags.clone();
+        args.extend([
+            "-MMD".to_string(),
+            "-MF".to_string(),
+            object.with_extension("d").to_string_lossy().to_string(),
+            "-c".to_string(),
+            source.to_string(),
+            "-o".to_string(),
+            object.to_string_lossy().to_string(),
+        ]);
+        args
+    }
+}
+
+// Результат компиляции одного файла
+struct CompileOutcome {
+    success: bool,
+    compiled: bool,
+    output: String,
+    // Команда компиляции и потраченные ею ресурсы, если компилятор запускался
+    usage: Option<(String, ProcessUsage)>,
+}
+
+pub fn setup_globals_cc(
+    lua: Lua,
+    script_path: String,
+    target: &Option<String>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let globals = lua.globals();
+
+    // ================ Сборка C/C++ ================
+
+    // Таблица cc со сборкой исполняемых файлов и статических библиотек (GCC/Clang)
+    // local result = cc.executable({
+    //     name = "app",
+    //     sources = {"src/main.cpp", "src/util.cpp"},
+    //     include_dirs = {"include"},
+    //     defines = {"NDEBUG", VERSION = "\"1.2\""},
+    //     flags = {"-std=c++20", "-O2", "-Wall"},
+    //     libs = {"pthread"},
+    //     compile_commands = true,
+    // })
+    // local lib = cc.static_library({name = "core", sources = {"core/a.c", "core/b.c"}})
+    // Файлы компилируются параллельно; при повторном запуске пересобираются только файлы,
+    // у которых изменился исходник, один из заголовков (по depfile от -MMD) или флаги
+    // Опции: name, sources, include_dirs, defines (массив или таблица имя = значение), flags,
+    //        link_flags, lib_dirs, libs, compiler (по умолчанию $CXX/c++ или $CC/cc),
+    //        build_dir (по умолчанию "build"), jobs (по умолчанию значение --jobs), quiet (boolean),
+    //        compile_commands (true - build_dir/compile_commands.json, или путь к файлу)
+    // Возвращает: таблица {success, output, objects, compiled, up_to_date, linked, errors, warnings,
+    //             diagnostics, duration}
+    let cc = lua.create_table()?;
+    let executable = lua.create_async_function(|lua, spec: mlua::Table| async move {
+        build_target(&lua, &spec, ArtifactKind::Executable).await
+    })?;
+    cc.set("executable", executable)?;
+    let static_library = lua.create_async_function(|lua, spec: mlua::Table| async move {
+        build_target(&lua, &spec, ArtifactKind::StaticLibrary).await
+    })?;
+    cc.set("static_library", static_library)?;
+    globals.set("cc", cc)?;
+
+    Ok(())
+}
+
+// ================ Вспомогательные функции ================
+
+async fn build_target(lua: &Lua, spec: &mlua::Table, kind: ArtifactKind) -> mlua::Result<mlua::Table> {
+    let target = CcTarget::from_table(spec)?;
+    let start_time = Instant::now();
+    let object_dir = target.build_dir.join("obj").join(&target.name);
+
+    let objects: Vec<PathBuf> = target.sources.iter().map(|source| object_path(&object_dir, source)).collect();
+    let total = target.sources.len();
+
+    if let Some(path) = &target.compile_commands {
+        write_compile_commands(&target, &objects, path)
+            .map_err(|e| mlua::Error::external(format!("Failed to write {}: {}", path.display(), e)))?;
+    }
+
+    let target = Arc::new(target);
+    let jobs: Vec<(usize, String, PathBuf)> = target
+        .sources
+        .iter()
+        .cloned()
+        .zip(objects.iter().cloned())
+        .enumerate()
+        .map(|(index, (source, object))| (index + 1, source, object))
+        .collect();
+    let outcomes: Vec<CompileOutcome> = stream::iter(jobs)
+        .map(|(index, source, object)| {
+            let target = target.clone();
+            async move { compile_source(&target, &source, &object, index, total).await }
+        })
+        .buffered(target.jobs)
+        .collect()
+        .await;
+
+    for (command, usage) in outcomes.iter().filter_map(|outcome| outcome.usage.as_ref()) {
+        record_usage(lua, command, *usage);
+    }
+    let compiled = outcomes.iter().filter(|outcome| outcome.compiled).count();
+    let compile_ok = outcomes.iter().all(|outcome| outcome.success);
+    let mut output: String = outcomes.iter().map(|outcome| outcome.output.as_str()).collect();
+
+    let artifact = target.output_path(kind);
+    let mut linked = false;
+    let mut success = compile_ok;
+    if compile_ok {
+        let (link_program, link_args) = link_command(&target, kind, &objects, &artifact);
+        if needs_link(&artifact, &objects, &link_program, &link_args, compiled > 0) {
+            if !target.quiet {
+                println!("{}", format!("Linking {}", artifact.display()).cyan());
+            }
+            let _ = std::fs::remove_file(&artifact);
+            let (result, usage) = command_output(Command::new(&link_program).args(&link_args))
+                .await
+                .map_err(|e| mlua::Error::external(format!("Failed to run {}: {}", link_program, e)))?;
+            record_usage(lua, &command_line(&link_program, &link_args), usage);
+            let link_output = format!(
+                "{}{}",
+                String::from_utf8_lossy(&result.stdout),
+                String::from_utf8_lossy(&result.stderr)
+            );
+            if !link_output.is_empty() && !target.quiet {
+                eprint!("{}", link_output);
+            }
+            output.push_str(&link_output);
+            success = result.status.success();
+            linked = true;
+            if success {
+                let _ = std::fs::write(command_file(&artifact), command_line(&link_program, &link_args));
+            }
+        }
+    }
+
+    let summary = diagnostics_summary(lua, &output)?;
+    let result = lua.create_table()?;
+    result.set("success", success)?;
+    result.set("output", artifact.to_string_lossy().to_string())?;
+    result.set(
+        "objects",
+        objects.iter().map(|object| object.to_string_lossy().to_string()).collect::<Vec<_>>(),
+    )?;
+    result.set("compiled", compiled)?;
+    result.set("up_to_date", total - compiled)?;
+    result.set("linked", linked)?;
+    result.set("errors", summary.get::<u32>("errors")?)?;
+    result.set("warnings", summary.get::<u32>("warnings")?)?;
+    result.set("diagnostics", summary.get::<mlua::Table>("diagnostics")?)?;
+    result.set("duration", start_time.elapsed().as_secs_f64())?;
+    Ok(result)
+}
+
+async fn compile_source(target: &CcTarget, source: &str, object: &Path, index: usize, total: usize) -> CompileOutcome {
+    let args = target.compile_args(source, object);
+    let command = command_line(&target.compiler, &args);
+
+    if is_up_to_date(source, object, &command) {
+        return CompileOutcome {
+            success: true,
+            compiled: false,
+            output: String::new(),
+            usage: None,
+        };
+    }
+
+    let _token = jobserver().acquire().await;
+    if !target.quiet {
+        println!("{}", format!("[{}/{}] Compiling {}", index, total, source).cyan());
+    }
+
+    if let Some(parent) = object.parent()
+        && let Err(e) = std::fs::create_dir_all(parent)
+    {
+        return CompileOutcome {
+            success: false,
+            compiled: true,
+            output: format!("Failed to create {}: {}\n", parent.display(), e),
+            usage: None,
+        };
+    }
+
+    let result = command_output(Command::new(&target.compiler).args(&args)).await;
+    match result {
+        Ok((result, usage)) => {
+            let output = format!(
+                "{}{}",
+                String::from_utf8_lossy(&result.stdout),
+                String::from_utf8_lossy(&result.stderr)
+            );
+            if !output.is_empty() && !target.quiet {
+                eprint!("{}", output);
+            }
+            if result.status.success() {
+                let _ = std::fs::write(command_file(object), &command);
+            } else {
+                // Объектный файл от неудачной компиляции не должен считаться актуальным
+                let _ = std::fs::remove_file(object);
+            }
+            CompileOutcome {
+                success: result.status.success(),
+                compiled: true,
+                output,
+                usage: Some((command, usage)),
+            }
+        }
+        Err(e) => {
+            let output = format!("Failed to run {}: {}\n", target.compiler, e);
+            if !target.quiet {
+                eprint!("{}", output.red());
+            }
+            CompileOutcome {
+                success: false,
+                compiled: true,
+                output,
+                usage: None,
+            }
+        }
+    }
+}
+
+fn link_command(target: &CcTarget, kind: ArtifactKind, objects: &[PathBuf], artifact: &Path) -> (String, Vec<String>) {
+    let objects = objects.iter().map(|object| object.to_string_lossy().to_string());
+    match kind {
+        ArtifactKind::Executable => {
+            let mut args: Vec<String> = objects.collect();
+            args.extend(["-o".to_string(), artifact.to_string_lossy().to_string()]);
+            args.extend(target.link_flags.iter().cloned());
+            (target.compiler.clone(), args)
+        }
+        ArtifactKind::StaticLibrary => {
+            let mut args = vec!["rcs".to_string(), artifact.to_string_lossy().to_string()];
+            args.extend(objects);
+            (std::env::var("AR").unwrap_or_else(|_| "ar".to_string()), args)
+        }
+    }
+}
+
+// Объектный файл актуален, если он новее исходника и всех заголовков из depfile,
+// а команда компиляции не изменилась
+fn is_up_to_date(source: &str, object: &Path, command: &str) -> bool {
+    let Some(object_time) = modified(object) else {
+        return false;
+    };
+    if std::fs::read_to_string(command_file(object)).ok().as_deref() != Some(command) {
+        return false;
+    }
+    let dependencies = match std::fs::read_to_string(object.with_extension("d")) {
+        Ok(depfile) => parse_depfile(&depfile),
+        Err(_) => vec![source.to_string()],
+    };
+    dependencies
+        .iter()
+        .all(|dependency| modified(Path::new(dependency)).is_some_and(|time| time <= object_time))
+}
+
+fn needs_link(artifact: &Path, objects: &[PathBuf], program: &str, args: &[String], recompiled: bool) -> bool {
+    if recompiled {
+        return true;
+    }
+    let Some(artifact_time) = modified(artifact) else {
+        return true;
+    };
+    if std::fs::read_to_string(command_file(artifact)).ok() != Some(command_line(program, args)) {
+        return true;
+    }
+    objects
+        .iter()
+        .any(|object| modified(object).is_none_or(|time| time > artifact_time))
+}
+
+// Разбирает depfile формата make: "obj.o: src.cpp include/a.h \
+//  include/b.h" (пробелы в путях экранируются обратной косой чертой)
+fn parse_depfile(content: &str) -> Vec<String> {
+    let content = content.replace("\\\r\n", " ").replace("\\\n", " ");
+    let Some(rule) = content.lines().next() else {
+        return Vec::new();
+    };
+    // Цель отделяется двоеточием с пробелом после него (двоеточие диска Windows за ним не следует)
+    let Some(position) = rule.find(": ") else {
+        return Vec::new();
+    };
+
+    let mut dependencies = Vec::new();
+    let mut current = String::new();
+    let mut chars = rule[position + 2..].chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&' ') => {
+                current.push(' ');
+                chars.next();
+            }
+            ' ' | '\t' => {
+                if !current.is_empty() {
+                    dependencies.push(std::mem::take(&mut current));
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        dependencies.push(current);
+    }
+    dependencies
+}
+
+// build/obj/app/src/main.cpp.o; ".." в пути заменяется на "__", чтобы не выйти за пределы каталога
+fn object_path(object_dir: &Path, source: &str) -> PathBuf {
+    let mut path = object_dir.to_path_buf();
+    for component in Path::new(source).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::ParentDir => path.push("__"),
+            _ => {}
+        }
+    }
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".o");
+    path.set_file_name(file_name);
+    path
+}
+
+// Файл рядом с результатом, в котором хранится команда его сборки
+fn command_file(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".cmd");
+    path.with_file_name(file_name)
+}
+
+fn command_line(program: &str, args: &[String]) -> String {
+    std::iter::once(program).chain(args.iter().map(String::as_str)).collect::<Vec<_>>().join(" ")
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+// Принимает массив {"DEBUG", "LEVEL=2"} или таблицу {DEBUG = true, VERSION = "1.2"}
+fn defines(value: Value) -> mlua::Result<Vec<String>> {
+    let Value::Table(table) = value else {
+        return string_list(value, "defines").map(|names| names.into_iter().map(|d| format!("-D{}", d)).collect());
+    };
+
+    let mut flags = Vec::new();
+    for pair in table.pairs::<Value, Value>() {
+        let (key, value) = pair?;
+        let define = match (key, value) {
+            (Value::Integer(_), Value::String(name)) => name.to_str()?.to_string(),
+            (Value::String(name), Value::Boolean(true)) => name.to_str()?.to_string(),
+            (Value::String(_), Value::Boolean(false)) => continue,
+            (Value::String(name), value) => format!("{}={}", name.to_str()?, value.to_string()?),
+            _ => return Err(mlua::Error::external("cc: invalid entry in 'defines'")),
+        };
+        flags.push(format!("-D{}", define));
+    }
+    flags.sort();
+    Ok(flags)
+}
+
+// Добавляет команды компиляции цели в compile_commands.json, сохраняя записи других файлов
+fn write_compile_commands(target: &CcTarget, objects: &[PathBuf], path: &Path) -> std::io::Result<()> {
+    let directory = std::env::current_dir()?;
+    let absolute = |file: &str| directory.join(file).to_string_lossy().to_string();
+
+    let mut entries: Vec<JsonValue> = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+
+    let sources: Vec<String> = target.sources.iter().map(|source| absolute(source)).collect();
+    entries.retain(|entry| !entry["file"].as_str().is_some_and(|file| sources.iter().any(|s| s == file)));
+
+    for ((source, file), object) in target.sources.iter().zip(&sources).zip(objects) {
+        let mut arguments = vec![target.compiler.clone()];
+        arguments.extend(target.compile_args(source, object));
+        entries.push(json!({
+            "directory": directory.to_string_lossy(),
+            "arguments": arguments,
+            "file": file,
+            "output": absolute(&object.to_string_lossy()),
+        }));
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let content = serde_json::to_string_pretty(&entries).map_err(std::io::Error::other)?;
+    std::fs::write(path, content)
+}
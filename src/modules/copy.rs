@@ -0,0 +1,746 @@
+use crate::modules::glob::{PatternList, relative_path};
+use crate::modules::walk::{gitignore_option, walk_builder};
+use mlua::{IntoLua, Lua, Value};
+use std::fs::{self, File, FileTimes};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+// Когда перезаписывать существующий файл назначения
+#[derive(Clone, Copy)]
+pub(crate) enum Overwrite {
+    Always,
+    Never,
+    // Только если исходный файл изменен позже
+    Newer,
+    // Только если отличается размер или содержимое
+    Different,
+}
+
+// Что делать с символическими ссылками в исходной папке
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Symlinks {
+    // Копировать то, на что указывает ссылка
+    Follow,
+    // Создавать такую же ссылку
+    Copy,
+    Skip,
+}
+
+// Какие файлы и папки попадают в копию
+#[derive(Clone)]
+pub(crate) struct CopyFilter {
+    include: PatternList,
+    exclude: PatternList,
+    include_dirs: PatternList,
+    exclude_dirs: PatternList,
+    pub(crate) symlinks: Symlinks,
+    gitignore: bool,
+}
+
+// Параметры записи файлов
+#[derive(Clone, Copy)]
+pub(crate) struct WriteOptions {
+    pub(crate) overwrite: Overwrite,
+    pub(crate) preserve_permissions: bool,
+    pub(crate) preserve_mtime: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum EntryKind {
+    Dir,
+    File,
+    Symlink,
+}
+
+// Элемент исходной папки, прошедший фильтры
+pub(crate) struct CopyEntry {
+    // Путь относительно исходной папки с разделителями "/"
+    pub(crate) relative: String,
+    pub(crate) source: PathBuf,
+    pub(crate) kind: EntryKind,
+}
+
+// Результат записи одного файла
+pub(crate) enum Outcome {
+    Copied(u64),
+    Skipped(&'static str),
+}
+
+impl CopyFilter {
+    // Опции include, exclude, include_dirs, exclude_dirs, symlinks и gitignore
+    pub(crate) fn from_table(options: Option<&mlua::Table>) -> mlua::Result<Self> {
+        let get = |name: &str| match options {
+            Some(options) => options.get::<Value>(name),
+            None => Ok(Value::Nil),
+        };
+        let symlinks = match get("symlinks")? {
+            Value::Nil => Symlinks::Follow,
+            Value::String(mode) => match mode.to_str()?.as_ref() {
+                "follow" => Symlinks::Follow,
+                "copy" => Symlinks::Copy,
+                "skip" => Symlinks::Skip,
+                other => {
+                    return Err(mlua::Error::external(format!(
+                        "Unknown symlinks mode '{}' (expected follow, copy or skip)",
+                        other
+                    )));
+                }
+            },
+            _ => return Err(mlua::Error::external("Option 'symlinks' must be a string")),
+        };
+        Ok(Self {
+            include: PatternList::from_value(get("include")?, "include")?,
+            exclude: PatternList::from_value(get("exclude")?, "exclude")?,
+            include_dirs: PatternList::from_value(get("include_dirs")?, "include_dirs")?,
+            exclude_dirs: PatternList::from_value(get("exclude_dirs")?, "exclude_dirs")?,
+            symlinks,
+            gitignore: gitignore_option(options)?,
+        })
+    }
+
+    // Пустые папки копируются, только если не заданы фильтры include и include_dirs
+    fn keeps_empty_dirs(&self) -> bool {
+        self.include.is_empty() && self.include_dirs.is_empty()
+    }
+
+    fn is_file_included(&self, relative: &str) -> bool {
+        if !self.include.is_empty() && !self.include.matches(relative) {
+            return false;
+        }
+        if self.exclude.matches(relative) {
+            return false;
+        }
+        // Файл должен лежать внутри одной из папок include_dirs (на любом уровне)
+        self.include_dirs.is_empty()
+            || relative
+                .match_indices('/')
+                .any(|(index, _)| self.include_dirs.matches(&relative[..index]))
+    }
+}
+
+impl WriteOptions {
+    // Опции overwrite, preserve_permissions и preserve_mtime
+    pub(crate) fn from_table(options: Option<&mlua::Table>, default_overwrite: Overwrite) -> mlua::Result<Self> {
+        let Some(options) = options else {
+            return Ok(Self {
+                overwrite: default_overwrite,
+                preserve_permissions: true,
+                preserve_mtime: false,
+            });
+        };
+        let overwrite = match options.get::<Option<String>>("overwrite")?.as_deref() {
+            None => default_overwrite,
+            Some("always") => Overwrite::Always,
+            Some("never") => Overwrite::Never,
+            Some("newer") => Overwrite::Newer,
+            Some("different") => Overwrite::Different,
+            Some(other) => {
+                return Err(mlua::Error::external(format!(
+                    "Unknown overwrite policy '{}' (expected always, never, newer or different)",
+                    other
+                )));
+            }
+        };
+        Ok(Self {
+            overwrite,
+            preserve_permissions: options.get::<Option<bool>>("preserve_permissions")?.unwrap_or(true),
+            preserve_mtime: options.get::<Option<bool>>("preserve_mtime")?.unwrap_or(false),
+        })
+    }
+}
+
+pub fn setup_globals_copy(
+    lua: Lua,
+    script_path: String,
+    target: &Option<String>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let globals = lua.globals();
+
+    // ================ Копирование папок ================
+
+    // Рекурсивно копирует директорию со всем содержимым
+    // copy_dir("src_folder", "dest_folder")
+    // copy_dir("project", "backup", "obfuscation")        -- без папок с именем obfuscation
+    // local ok, report = copy_dir("assets", "dist/assets", {
+    //     include = {"*.png", "*.{json,css}"},
+    //     exclude_dirs = {"raw", "**/node_modules"},
+    //     overwrite = "newer",
+    //     preserve_mtime = true,
+    //     progress = function(info) println(info.index .. "/" .. info.total .. " " .. info.path) end,
+    // })
+    // Шаблоны без "/" сравниваются с именем файла или папки, остальные - с путем относительно src
+    // Опции: include, exclude (string | table) - шаблоны файлов, которые копируются / пропускаются
+    //        include_dirs (string | table) - копировать только файлы внутри этих папок
+    //        exclude_dirs (string | table) - папки, которые не копируются целиком
+    //        overwrite (string) - "always" (по умолчанию), "never", "newer", "different"
+    //        preserve_permissions (boolean, по умолчанию true) - копировать права доступа
+    //        preserve_mtime (boolean, по умолчанию false) - сохранять время изменения файлов
+    //        symlinks (string) - "follow" (по умолчанию, копировать содержимое), "copy" (создать ссылку), "skip"
+    //        gitignore (boolean) - пропускать пути из .gitignore, .ignore и .solverignore
+    //        progress (function) - вызывается для каждого файла с {path, status, reason, index, total, bytes}
+    // Возвращает: true, table {copied, skipped, bytes} (пути относительно src) | false, string (ошибка)
+    let copy_dir = lua.create_async_function(
+        |lua, (src, dest, options, extra): (String, String, Value, Option<mlua::Table>)| async move {
+            // Старая форма вызова: третьим аргументом имя игнорируемой папки
+            let (dir_ignore, options) = match options {
+                Value::String(_) => (options, extra),
+                Value::Table(options) => (Value::Nil, Some(options)),
+                _ => (Value::Nil, extra),
+            };
+            let mut filter = CopyFilter::from_table(options.as_ref())?;
+            if !dir_ignore.is_nil() {
+                filter.exclude_dirs = PatternList::from_value(dir_ignore, "dir_ignore")?;
+            }
+            let write_options = WriteOptions::from_table(options.as_ref(), Overwrite::Always)?;
+            let progress = match &options {
+                Some(options) => options.get::<Option<mlua::Function>>("progress")?,
+                None => None,
+            };
+
+            let src = PathBuf::from(src);
+            let dest = PathBuf::from(dest);
+            let symlinks = filter.symlinks;
+            let entries = match collect_entries(&src, Arc::new(filter)).await {
+                Ok(entries) => entries,
+                Err(e) => return Ok((false, format!("{}: {}", src.display(), e).into_lua(&lua)?)),
+            };
+            if let Err(e) = tokio::fs::create_dir_all(&dest).await {
+                return Ok((false, format!("{}: {}", dest.display(), e).into_lua(&lua)?));
+            }
+
+            let total = entries.iter().filter(|entry| entry.kind != EntryKind::Dir).count();
+            let mut index = 0;
+            let mut bytes = 0;
+            let copied = lua.create_table()?;
+            let skipped = lua.create_table()?;
+            for entry in entries {
+                let target = dest.join(&entry.relative);
+                let result = match entry.kind {
+                    EntryKind::Dir => {
+                        if let Err(e) = tokio::fs::create_dir_all(&target).await {
+                            return Ok((false, format!("{}: {}", target.display(), e).into_lua(&lua)?));
+                        }
+                        continue;
+                    }
+                    EntryKind::File => {
+                        let source = entry.source.clone();
+                        let target = target.clone();
+                        tokio::task::spawn_blocking(move || copy_file_entry(&source, &target, write_options))
+                            .await
+                            .map_err(mlua::Error::external)?
+                    }
+                    EntryKind::Symlink if symlinks == Symlinks::Skip => Ok(Outcome::Skipped("symlink")),
+                    EntryKind::Symlink => copy_symlink(&entry.source, &target, write_options),
+                };
+
+                index += 1;
+                let (status, reason) = match result {
+                    Ok(Outcome::Copied(size)) => {
+                        bytes += size;
+                        copied.push(entry.relative.as_str())?;
+                        ("copied", None)
+                    }
+                    Ok(Outcome::Skipped(reason)) => {
+                        skipped.push(entry.relative.as_str())?;
+                        ("skipped", Some(reason))
+                    }
+                    Err(e) => return Ok((false, format!("{}: {}", entry.source.display(), e).into_lua(&lua)?)),
+                };
+
+                if let Some(progress) = &progress {
+                    let info = lua.create_table()?;
+                    info.set("path", entry.relative.as_str())?;
+                    info.set("status", status)?;
+                    info.set("reason", reason)?;
+                    info.set("index", index)?;
+                    info.set("total", total)?;
+                    info.set("bytes", bytes)?;
+                    progress.call_async::<()>(info).await?;
+                }
+            }
+
+            let report = lua.create_table()?;
+            report.set("copied", copied)?;
+            report.set("skipped", skipped)?;
+            report.set("bytes", bytes)?;
+            Ok((true, Value::Table(report)))
+        },
+    )?;
+    globals.set("copy_dir", copy_dir)?;
+
+    // ================ Синхронизация папок ================
+
+    // Делает dest копией src, копируя только измененные файлы (как rsync)
+    // local ok, report = sync_dir("build/site", "/var/www/site", {delete = true})
+    // local ok, plan = sync_dir("dist", "deploy", {compare = "hash", dry_run = true, exclude = "*.map"})
+    // for _, path in ipairs(plan.updated) do println("будет обновлен: " .. path) end
+    // Опции: compare (string) - "mtime" (по умолчанию, размер и время изменения) или "hash" (содержимое)
+    //        modify_window (number, по умолчанию 0) - допустимая разница времени изменения в секундах,
+    //        как --modify-window у rsync (2 для FAT)
+    //        delete (boolean, по умолчанию false) - удалять из dest файлы и папки, которых нет в src
+    //        dry_run (boolean, по умолчанию false) - только вычислить изменения, ничего не записывая
+    //        include, exclude, include_dirs, exclude_dirs, symlinks, gitignore - как у copy_dir
+    //        (исключенные пути в dest не удаляются)
+    // Время изменения и права доступа файлов сохраняются, чтобы следующий вызов не копировал их снова
+    // Возвращает: true, table {added, updated, deleted, unchanged} (пути относительно src/dest) | false, string (ошибка)
+    let sync_dir = lua.create_async_function(|lua, (src, dest, options): (String, String, Option<mlua::Table>)| async move {
+        let filter = Arc::new(CopyFilter::from_table(options.as_ref())?);
+        let (compare, modify_window, delete, dry_run) = match &options {
+            Some(options) => (
+                options.get::<Option<String>>("compare")?,
+                options.get::<Option<f64>>("modify_window")?.unwrap_or(0.0),
+                options.get::<Option<bool>>("delete")?.unwrap_or(false),
+                options.get::<Option<bool>>("dry_run")?.unwrap_or(false),
+            ),
+            None => (None, 0.0, false, false),
+        };
+        let modify_window = Duration::try_from_secs_f64(modify_window)
+            .map_err(|_| mlua::Error::external(format!("Invalid modify_window: {}", modify_window)))?;
+        let compare = match compare.as_deref() {
+            None | Some("mtime") => Compare::Mtime(modify_window),
+            Some("hash") => Compare::Hash,
+            Some(other) => {
+                return Err(mlua::Error::external(format!(
+                    "Unknown compare mode '{}' (expected mtime or hash)",
+                    other
+                )));
+            }
+        };
+
+        let (src, dest) = (PathBuf::from(src), PathBuf::from(dest));
+        let src_entries = match collect_entries(&src, filter.clone()).await {
+            Ok(entries) => entries,
+            Err(e) => return Ok((false, format!("{}: {}", src.display(), e).into_lua(&lua)?)),
+        };
+        let dest_entries = if delete && dest.is_dir() {
+            // Ссылки в dest не разыменовываются: иначе delete удалял бы файлы там, куда они указывают
+            let dest_filter = Arc::new(CopyFilter {
+                symlinks: Symlinks::Copy,
+                ..(*filter).clone()
+            });
+            match collect_entries(&dest, dest_filter).await {
+                Ok(entries) => entries,
+                Err(e) => return Ok((false, format!("{}: {}", dest.display(), e).into_lua(&lua)?)),
+            }
+        } else {
+            Vec::new()
+        };
+
+        let symlinks = filter.symlinks;
+        let dest_root = dest.clone();
+        let changes = tokio::task::spawn_blocking(move || {
+            sync_entries(src_entries, dest_entries, &dest_root, symlinks, compare, dry_run)
+        })
+        .await
+        .map_err(mlua::Error::external)?;
+        let changes = match changes {
+            Ok(changes) => changes,
+            Err(e) => return Ok((false, format!("{}: {}", dest.display(), e).into_lua(&lua)?)),
+        };
+
+        let report = lua.create_table()?;
+        report.set("added", changes.added)?;
+        report.set("updated", changes.updated)?;
+        report.set("deleted", changes.deleted)?;
+        report.set("unchanged", changes.unchanged)?;
+        Ok((true, Value::Table(report)))
+    })?;
+    globals.set("sync_dir", sync_dir)?;
+
+    Ok(())
+}
+
+// ================ Вспомогательные функции ================
+
+// Обходит исходную папку и отбирает элементы по фильтрам
+// Возвращает: папки и файлы в порядке обхода (папка раньше своего содержимого)
+pub(crate) async fn collect_entries(src: &Path, filter: Arc<CopyFilter>) -> std::io::Result<Vec<CopyEntry>> {
+    if !src.is_dir() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotADirectory, "not a directory"));
+    }
+    let src = src.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let root = src.clone();
+        let dir_filter = filter.clone();
+        let walker = walk_builder(&src, filter.gitignore)
+            .follow_links(filter.symlinks == Symlinks::Follow)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .filter_entry(move |entry| {
+                let is_dir = entry.file_type().is_some_and(|file_type| file_type.is_dir());
+                entry.depth() == 0 || !is_dir || !dir_filter.exclude_dirs.matches(&relative_path(entry.path(), &root))
+            })
+            .build();
+
+        let mut entries = Vec::new();
+        for entry in walker {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => match e.io_error() {
+                    Some(error) => return Err(std::io::Error::new(error.kind(), e.to_string())),
+                    None => continue,
+                },
+            };
+            if entry.depth() == 0 {
+                continue;
+            }
+            let Some(file_type) = entry.file_type() else {
+                continue;
+            };
+            let relative = relative_path(entry.path(), &src);
+            let kind = if file_type.is_dir() {
+                if !filter.keeps_empty_dirs() {
+                    continue;
+                }
+                EntryKind::Dir
+            } else if file_type.is_symlink() {
+                EntryKind::Symlink
+            } else {
+                EntryKind::File
+            };
+            if kind != EntryKind::Dir && !filter.is_file_included(&relative) {
+                continue;
+            }
+            entries.push(CopyEntry {
+                relative,
+                source: entry.into_path(),
+                kind,
+            });
+        }
+        Ok(entries)
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+// Копирует файл с учетом политики перезаписи
+pub(crate) fn copy_file_entry(source: &Path, dest: &Path, options: WriteOptions) -> std::io::Result<Outcome> {
+    if let Ok(dest_metadata) = fs::symlink_metadata(dest) {
+        match options.overwrite {
+            Overwrite::Always => {}
+            Overwrite::Never => return Ok(Outcome::Skipped("exists")),
+            Overwrite::Newer => {
+                if fs::metadata(source)?.modified()? <= dest_metadata.modified()? {
+                    return Ok(Outcome::Skipped("not newer"));
+                }
+            }
+            Overwrite::Different => {
+                if same_contents(source, dest)? {
+                    return Ok(Outcome::Skipped("identical"));
+                }
+            }
+        }
+        // Не пишем сквозь ссылку, оставшуюся от копирования с symlinks = "copy"
+        if dest_metadata.file_type().is_symlink() {
+            fs::remove_file(dest)?;
+        }
+    }
+
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let size = if options.preserve_permissions {
+        fs::copy(source, dest)?
+    } else {
+        std::io::copy(&mut File::open(source)?, &mut File::create(dest)?)?
+    };
+    if options.preserve_mtime {
+        let metadata = fs::metadata(source)?;
+        let times = FileTimes::new()
+            .set_accessed(metadata.accessed()?)
+            .set_modified(metadata.modified()?);
+        File::options().write(true).open(dest)?.set_times(times)?;
+    }
+    Ok(Outcome::Copied(size))
+}
+
+// Создает в dest такую же символическую ссылку, как source
+pub(crate) fn copy_symlink(source: &Path, dest: &Path, options: WriteOptions) -> std::io::Result<Outcome> {
+    let link = fs::read_link(source)?;
+    if let Ok(dest_metadata) = fs::symlink_metadata(dest) {
+        if let Overwrite::Never = options.overwrite {
+            return Ok(Outcome::Skipped("exists"));
+        }
+        if dest_metadata.file_type().is_symlink() && fs::read_link(dest)? == link {
+            return Ok(Outcome::Skipped("identical"));
+        }
+        fs::remove_file(dest)?;
+    }
+
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(&link, dest)?;
+    #[cfg(windows)]
+    if source.is_dir() {
+        std::os::windows::fs::symlink_dir(&link, dest)?;
+    } else {
+        std::os::windows::fs::symlink_file(&link, dest)?;
+    }
+    Ok(Outcome::Copied(0))
+}
+
+// Сравнивает размер и содержимое двух файлов
+pub(crate) fn same_contents(a: &Path, b: &Path) -> std::io::Result<bool> {
+    let (metadata_a, metadata_b) = (fs::metadata(a)?, fs::metadata(b)?);
+    if metadata_a.len() != metadata_b.len() || metadata_a.is_dir() != metadata_b.is_dir() {
+        return Ok(false);
+    }
+
+    let (mut file_a, mut file_b) = (File::open(a)?, File::open(b)?);
+    let mut buffer_a = vec![0u8; 64 * 1024];
+    let mut buffer_b = vec![0u8; 64 * 1024];
+    loop {
+        let read = file_a.read(&mut buffer_a)?;
+        if read == 0 {
+            return Ok(true);
+        }
+        file_b.read_exact(&mut buffer_b[..read])?;
+        if buffer_a[..read] != buffer_b[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+// Как sync_dir определяет измененные файлы
+#[derive(Clone, Copy)]
+enum Compare {
+    // Размер и время изменения с допустимой разницей времени
+    Mtime(Duration),
+    // Содержимое
+    Hash,
+}
+
+// Изменения, найденные sync_dir
+#[derive(Default)]
+struct SyncChanges {
+    added: Vec<String>,
+    updated: Vec<String>,
+    deleted: Vec<String>,
+    unchanged: Vec<String>,
+}
+
+// Копирует новые и измененные элементы src в dest и удаляет лишние элементы dest_entries
+fn sync_entries(
+    src_entries: Vec<CopyEntry>,
+    dest_entries: Vec<CopyEntry>,
+    dest: &Path,
+    symlinks: Symlinks,
+    compare: Compare,
+    dry_run: bool,
+) -> std::io::Result<SyncChanges> {
+    let write_options = WriteOptions {
+        overwrite: Overwrite::Always,
+        preserve_permissions: true,
+        preserve_mtime: true,
+    };
+    let mut changes = SyncChanges::default();
+    if !dry_run {
+        fs::create_dir_all(dest)?;
+    }
+
+    // Пути src вместе с папками, в которых лежат файлы (они не удаляются из dest)
+    let mut keep = std::collections::HashSet::new();
+    for entry in &src_entries {
+        keep.insert(entry.relative.clone());
+        for (index, _) in entry.relative.match_indices('/') {
+            keep.insert(entry.relative[..index].to_string());
+        }
+    }
+
+    for entry in &src_entries {
+        let target = dest.join(&entry.relative);
+        // Папки (и папка, в которой лежит файл) должны быть настоящими: запись через ссылку
+        // в dest ушла бы за его пределы
+        if !dry_run {
+            let dir = match entry.kind {
+                EntryKind::Dir => Some(entry.relative.as_str()),
+                _ => entry.relative.rsplit_once('/').map(|(parent, _)| parent),
+            };
+            if let Some(dir) = dir {
+                create_real_dirs(dest, dir)?;
+            }
+        }
+        let exists = fs::symlink_metadata(&target).is_ok();
+        let changed = match entry.kind {
+            EntryKind::Dir => continue,
+            EntryKind::Symlink if symlinks == Symlinks::Skip => continue,
+            EntryKind::Symlink => !exists || fs::read_link(&target).ok() != Some(fs::read_link(&entry.source)?),
+            EntryKind::File => !exists || file_changed(&entry.source, &target, compare)?,
+        };
+
+        if !changed {
+            changes.unchanged.push(entry.relative.clone());
+            continue;
+        }
+        if !dry_run {
+            // Папка или ссылка на месте файла заменяется целиком
+            if exists && (entry.kind == EntryKind::Symlink || !fs::symlink_metadata(&target)?.is_file()) {
+                remove_path(&target)?;
+            }
+            match entry.kind {
+                EntryKind::Symlink => copy_symlink(&entry.source, &target, write_options)?,
+                _ => copy_file_entry(&entry.source, &target, write_options)?,
+            };
+        }
+        if exists {
+            changes.updated.push(entry.relative.clone());
+        } else {
+            changes.added.push(entry.relative.clone());
+        }
+    }
+
+    // Удаляем сверху вниз: содержимое удаленной папки отдельно не перечисляется
+    let mut removed_dirs: Vec<String> = Vec::new();
+    for entry in dest_entries {
+        if keep.contains(&entry.relative)
+            || removed_dirs.iter().any(|dir| entry.relative.starts_with(&format!("{}/", dir)))
+        {
+            continue;
+        }
+        if !dry_run {
+            remove_path(&entry.source)?;
+        }
+        if entry.kind == EntryKind::Dir {
+            removed_dirs.push(entry.relative.clone());
+        }
+        changes.deleted.push(entry.relative);
+    }
+    Ok(changes)
+}
+
+// Файл назначения отличается от исходного: по размеру и времени изменения или по содержимому
+fn file_changed(source: &Path, dest: &Path, compare: Compare) -> std::io::Result<bool> {
+    let dest_metadata = fs::symlink_metadata(dest)?;
+    if !dest_metadata.is_file() {
+        return Ok(true);
+    }
+    let modify_window = match compare {
+        Compare::Hash => return Ok(!same_contents(source, dest)?),
+        Compare::Mtime(modify_window) => modify_window,
+    };
+    let source_metadata = fs::metadata(source)?;
+    Ok(source_metadata.len() != dest_metadata.len()
+        || !same_mtime(source_metadata.modified()?, dest_metadata.modified()?, modify_window))
+}
+
+// Время изменения совпадает с точностью до modify_window
+fn same_mtime(a: SystemTime, b: SystemTime, modify_window: Duration) -> bool {
+    let difference = a.duration_since(b).or_else(|_| b.duration_since(a)).unwrap_or_default();
+    difference <= modify_window
+}
+
+// Создает папки relative (путь с "/") внутри dest по одной; ссылки и файлы на их месте
+// заменяются настоящими папками
+fn create_real_dirs(dest: &Path, relative: &str) -> std::io::Result<()> {
+    let mut current = dest.to_path_buf();
+    for part in relative.split('/').filter(|part| !part.is_empty()) {
+        current.push(part);
+        match fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.is_dir() => continue,
+            Ok(_) => remove_path(&current)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        fs::create_dir(&current)?;
+    }
+    Ok(())
+}
+
+// Удаляет файл, ссылку или папку целиком; уже удаленный путь не считается ошибкой
+pub(crate) fn remove_path(path: &Path) -> std::io::Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Пустая папка для теста внутри системной папки временных файлов
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("solver-copy-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn file_changed_compares_mtime_exactly_by_default() {
+        let root = test_dir("modify-window");
+        let (source, dest) = (root.join("source.txt"), root.join("dest.txt"));
+        fs::write(&source, "same").unwrap();
+        fs::write(&dest, "same").unwrap();
+        let modified = fs::metadata(&source).unwrap().modified().unwrap();
+        let times = FileTimes::new().set_modified(modified + Duration::from_secs(1));
+        File::options().write(true).open(&dest).unwrap().set_times(times).unwrap();
+
+        assert!(file_changed(&source, &dest, Compare::Mtime(Duration::ZERO)).unwrap());
+        assert!(!file_changed(&source, &dest, Compare::Mtime(Duration::from_secs(2))).unwrap());
+        assert!(!file_changed(&source, &dest, Compare::Hash).unwrap());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn sync_dir_replaces_symlinked_dirs_in_dest() {
+        let root = test_dir("sync-symlink");
+        let (src, dest, outside) = (root.join("src"), root.join("dest"), root.join("outside"));
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("sub/new.txt"), "new").unwrap();
+        fs::create_dir_all(&dest).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("victim.txt"), "keep").unwrap();
+        std::os::unix::fs::symlink(&outside, dest.join("sub")).unwrap();
+
+        let lua = Lua::new();
+        setup_globals_copy(lua.clone(), String::new(), &None, false).unwrap();
+        lua.globals().set("src", src.to_str().unwrap()).unwrap();
+        lua.globals().set("dest", dest.to_str().unwrap()).unwrap();
+        lua.load(r#"assert(sync_dir(src, dest, {delete = true, symlinks = "follow"}))"#)
+            .exec_async()
+            .await
+            .unwrap();
+
+        assert!(outside.join("victim.txt").exists());
+        assert!(!outside.join("new.txt").exists());
+        assert!(fs::symlink_metadata(dest.join("sub")).unwrap().is_dir());
+        assert_eq!(fs::read_to_string(dest.join("sub/new.txt")).unwrap(), "new");
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn sync_dir_deletes_symlinks_in_dest_not_their_targets() {
+        let root = test_dir("sync-delete-link");
+        let (src, dest, outside) = (root.join("src"), root.join("dest"), root.join("outside"));
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&dest).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("victim.txt"), "keep").unwrap();
+        std::os::unix::fs::symlink(&outside, dest.join("link")).unwrap();
+
+        let lua = Lua::new();
+        setup_globals_copy(lua.clone(), String::new(), &None, false).unwrap();
+        lua.globals().set("src", src.to_str().unwrap()).unwrap();
+        lua.globals().set("dest", dest.to_str().unwrap()).unwrap();
+        lua.load(r#"assert(sync_dir(src, dest, {delete = true, symlinks = "follow"}))"#)
+            .exec_async()
+            .await
+            .unwrap();
+
+        assert!(outside.join("victim.txt").exists());
+        assert!(fs::symlink_metadata(dest.join("link")).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,469 @@
+use crate::modules::process::call_output_callback;
+use crate::modules::text::strip_ansi_codes;
+use mlua::{Lua, UserData, UserDataMethods};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::sync::{Arc, LazyLock, Mutex};
+
+// GCC/Clang: "src/main.cpp:12:5: error: message [-Wflag]" (колонка может отсутствовать)
+static GCC_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<file>.+?):(?P<line>\d+):(?:(?P<column>\d+):)?\s+(?P<level>fatal error|error|warning|note|remark):\s+(?P<message>.*?)(?:\s+\[(?P<code>-[^\]]+)\])?$").unwrap()
+});
+
+// MSVC: "src\main.cpp(12,5): error C2065: 'x': undeclared identifier"
+static MSVC_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*(?P<file>.+?)\((?P<line>\d+)(?:,(?P<column>\d+))?\)\s*:\s*(?P<level>fatal error|error|warning|note)(?:\s+(?P<code>[A-Z]+\d+))?\s*:\s*(?P<message>.*)$").unwrap()
+});
+
+// Сообщения инструментов без позиции в файле:
+// "cc1plus: fatal error: x.cpp: No such file", "collect2: error: ld returned 1 exit status",
+// "LINK : fatal error LNK1104: cannot open file 'app.exe'", "main.obj : error LNK2019: ..."
+static TOOL_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<tool>[\w.+\-/\\]+?)\s*:\s*(?P<level>fatal error|error|warning)(?:\s+(?P<code>[A-Z]+\d+))?\s*:\s*(?P<message>.*)$").unwrap()
+});
+
+// Ошибки компоновщика GNU ld: "main.cpp:(.text+0x5): undefined reference to `foo()'"
+static LINKER_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<file>.+?):\(\.[^)]*\):\s+(?P<message>(?:undefined reference|multiple definition).*)$").unwrap()
+});
+
+// Строки-заголовки, относящиеся к следующему сообщению:
+// "main.cpp: In function 'int main()':", "In file included from a.h:3,", "/usr/bin/ld: a.o: in function `main':"
+static CONTEXT_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?:In file included from |\s+from |.+?: (?:In|in) (?:function|member function|instantiation|constructor|destructor|static member function|lambda)|.+?: At (?:global|top level))").unwrap()
+});
+
+// Одна диагностика компилятора
+#[derive(Clone, Default)]
+struct Diagnostic {
+    level: String,
+    fatal: bool,
+    file: Option<String>,
+    line: Option<u32>,
+    column: Option<u32>,
+    message: String,
+    code: Option<String>,
+    tool: String,
+    notes: Vec<Diagnostic>,
+    rendered: Vec<String>,
+}
+
+impl Diagnostic {
+    fn from_line(line: &str) -> Option<Self> {
+        let (captures, tool) = if let Some(captures) = GCC_PATTERN.captures(line) {
+            (captures, "gcc".to_string())
+        } else if let Some(captures) = MSVC_PATTERN.captures(line) {
+            (captures, "msvc".to_string())
+        } else if let Some(captures) = LINKER_PATTERN.captures(line) {
+            return Some(Self {
+                level: "error".to_string(),
+                file: Some(captures["file"].to_string()),
+                message: captures["message"].to_string(),
+                tool: "ld".to_string(),
+                rendered: vec![line.to_string()],
+                ..Default::default()
+            });
+        } else if let Some(captures) = TOOL_PATTERN.captures(line) {
+            let tool = captures["tool"].to_string();
+            (captures, tool)
+        } else {
+            return None;
+        };
+
+        let level = &captures["level"];
+        Some(Self {
+            level: level.trim_start_matches("fatal ").to_string(),
+            fatal: level.starts_with("fatal"),
+            file: captures.name("file").map(|m| m.as_str().to_string()),
+            line: captures.name("line").and_then(|m| m.as_str().parse().ok()),
+            column: captures.name("column").and_then(|m| m.as_str().parse().ok()),
+            message: captures["message"].to_string(),
+            code: captures.name("code").map(|m| m.as_str().to_string()),
+            tool,
+            notes: Vec::new(),
+            rendered: vec![line.to_string()],
+        })
+    }
+
+    fn to_table(&self, lua: &Lua) -> mlua::Result<mlua::Table> {
+        let table = lua.create_table()?;
+        table.set("level", self.level.clone())?;
+        table.set("fatal", self.fatal)?;
+        table.set("file", self.file.clone())?;
+        table.set("line", self.line)?;
+        table.set("column", self.column)?;
+        table.set("message", self.message.clone())?;
+        table.set("code", self.code.clone())?;
+        table.set("tool", self.tool.clone())?;
+        table.set("rendered", self.rendered.join("\n"))?;
+        let notes = lua.create_table()?;
+        for note in &self.notes {
+            notes.push(note.to_table(lua)?)?;
+        }
+        table.set("notes", notes)?;
+        Ok(table)
+    }
+}
+
+// Состояние построчного разбора вывода компилятора
+#[derive(Default)]
+struct ParserState {
+    pending: Option<Diagnostic>,
+    context: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl ParserState {
+    // Обрабатывает строку и возвращает диагностики, которые точно завершены
+    // (диагностика завершается следующим сообщением или посторонней строкой)
+    fn feed(&mut self, line: &str) -> Vec<Diagnostic> {
+        let line = strip_ansi_codes(line);
+        let line = line.trim_end();
+        let mut completed = Vec::new();
+
+        if let Some(mut diagnostic) = Diagnostic::from_line(line) {
+            // note после ошибки или предупреждения относится к ним
+            if diagnostic.level == "note"
+                && let Some(pending) = self.pending.as_mut()
+            {
+                pending.rendered.push(line.to_string());
+                pending.notes.push(diagnostic);
+                return completed;
+            }
+            completed.extend(self.pending.take());
+            if !self.context.is_empty() {
+                diagnostic.rendered.splice(0..0, self.context.drain(..));
+            }
+            self.pending = Some(diagnostic);
+        } else if CONTEXT_PATTERN.is_match(line) {
+            completed.extend(self.pending.take());
+            self.context.push(line.to_string());
+        } else if let Some(pending) = self.pending.as_mut().filter(|_| is_continuation(line)) {
+            // Фрагмент исходника, указатель ^~~ или "required from here" к текущему сообщению
+            if let Some(note) = pending.notes.last_mut() {
+                note.rendered.push(line.to_string());
+            }
+            pending.rendered.push(line.to_string());
+        } else {
+            completed.extend(self.pending.take());
+            self.context.clear();
+        }
+
+        self.diagnostics.extend(completed.iter().cloned());
+        completed
+    }
+
+    fn finish(&mut self) -> Option<Diagnostic> {
+        self.context.clear();
+        let diagnostic = self.pending.take()?;
+        self.diagnostics.push(diagnostic.clone());
+        Some(diagnostic)
+    }
+
+    fn result(&mut self, lua: &Lua) -> mlua::Result<mlua::Table> {
+        self.finish();
+        let result = lua.create_table()?;
+        let diagnostics = lua.create_table()?;
+        let mut counts: BTreeMap<&str, (u32, u32, u32)> = BTreeMap::new();
+        let (mut errors, mut warnings, mut notes) = (0, 0, 0);
+
+        for diagnostic in &self.diagnostics {
+            diagnostics.push(diagnostic.to_table(lua)?)?;
+            let file_counts = diagnostic.file.as_deref().map(|file| counts.entry(file).or_default());
+            match diagnostic.level.as_str() {
+                "error" => {
+                    errors += 1;
+                    if let Some(file_counts) = file_counts {
+                        file_counts.0 += 1;
+                    }
+                }
+                "warning" => {
+                    warnings += 1;
+                    if let Some(file_counts) = file_counts {
+                        file_counts.1 += 1;
+                    }
+                }
+                _ => {
+                    notes += 1;
+                    if let Some(file_counts) = file_counts {
+                        file_counts.2 += 1;
+                    }
+                }
+            }
+        }
+
+        let files = lua.create_table()?;
+        for (file, (file_errors, file_warnings, file_notes)) in counts {
+            let entry = lua.create_table()?;
+            entry.set("errors", file_errors)?;
+            entry.set("warnings", file_warnings)?;
+            entry.set("notes", file_notes)?;
+            files.set(file, entry)?;
+        }
+
+        result.set("diagnostics", diagnostics)?;
+        result.set("errors", errors)?;
+        result.set("warnings", warnings)?;
+        result.set("notes", notes)?;
+        result.set("files", files)?;
+        Ok(result)
+    }
+}
+
+// Строки с отступом, "  5 | code" или "file:1:2:   required from here" продолжают текущее сообщение
+fn is_continuation(line: &str) -> bool {
+    line.starts_with([' ', '\t']) || line.contains("required from") || line.contains("In instantiation of")
+}
+
+// Разбирает весь вывод компилятора целиком
+pub(crate) fn diagnostics_summary(lua: &Lua, text: &str) -> mlua::Result<mlua::Table> {
+    let mut state = ParserState::default();
+    for line in text.lines() {
+        state.feed(line);
+    }
+    state.result(lua)
+}
+
+// Разборщик вывода компилятора для использования в Lua
+pub struct DiagnosticParser {
+    state: Arc<Mutex<ParserState>>,
+    on_diagnostic: Option<mlua::Function>,
+    on_line: Option<mlua::Function>,
+}
+
+impl DiagnosticParser {
+    // Передает завершенные диагностики в on_diagnostic; возвращает причину прерывания, если она есть
+    async fn emit(
+        lua: &Lua,
+        on_diagnostic: Option<&mlua::Function>,
+        completed: Vec<Diagnostic>,
+    ) -> mlua::Result<Option<String>> {
+        let Some(on_diagnostic) = on_diagnostic else {
+            return Ok(None);
+        };
+        for diagnostic in completed {
+            let reason = call_output_callback(
+                on_diagnostic,
+                diagnostic.to_table(lua)?,
+                &diagnostic.rendered.join("\n"),
+                "Diagnostic callback error",
+            )
+            .await?;
+            if reason.is_some() {
+                return Ok(reason);
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl UserData for DiagnosticParser {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        // Обрабатывает одну строку вывода
+        // parser:feed("main.cpp:3:1: error: expected ';'")
+        // Возвращает: string | nil (причина прерывания, если on_diagnostic вернул false или "abort")
+        methods.add_async_method("feed", |lua, this, line: String| async move {
+            let completed = this.state.lock().unwrap().feed(&line);
+            DiagnosticParser::emit(&lua, this.on_diagnostic.as_ref(), completed).await
+        });
+
+        // Завершает разбор (последнее сообщение считается законченным)
+        // parser:finish()
+        methods.add_async_method("finish", |lua, this, ()| async move {
+            let completed: Vec<Diagnostic> = this.state.lock().unwrap().finish().into_iter().collect();
+            DiagnosticParser::emit(&lua, this.on_diagnostic.as_ref(), completed).await?;
+            Ok(())
+        });
+
+        // Возвращает собранные диагностики и счетчики
+        // local summary = parser:result()
+        // Возвращает: таблица {diagnostics, errors, warnings, notes, files = {[file] = {errors, warnings, notes}}}
+        methods.add_method("result", |lua, this, ()| this.state.lock().unwrap().result(lua));
+
+        // Возвращает функцию для task_run: строки разбираются на лету и передаются в on_line
+        // task_run("g++ -c main.cpp", parser:callback())
+        // Возвращает: function(line, stream)
+        methods.add_method("callback", |lua, this, ()| {
+            let state = this.state.clone();
+            let on_diagnostic = this.on_diagnostic.clone();
+            let on_line = this.on_line.clone();
+            lua.create_async_function(move |lua, (line, stream): (String, Option<String>)| {
+                let state = state.clone();
+                let on_diagnostic = on_diagnostic.clone();
+                let on_line = on_line.clone();
+                async move {
+                    // Прерывание передается в task_run как false и причина
+                    if let Some(on_line) = &on_line {
+                        let reason = call_output_callback(on_line, (line.clone(), stream), &line, "Line callback error").await?;
+                        if reason.is_some() {
+                            return Ok((Some(false), reason));
+                        }
+                    }
+                    let completed = state.lock().unwrap().feed(&line);
+                    match DiagnosticParser::emit(&lua, on_diagnostic.as_ref(), completed).await? {
+                        Some(reason) => Ok((Some(false), Some(reason))),
+                        None => Ok((None, None)),
+                    }
+                }
+            })
+        });
+    }
+}
+
+pub fn setup_globals_diagnostics(
+    lua: Lua,
+    script_path: String,
+    target: &Option<String>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let globals = lua.globals();
+
+    // ================ Разбор диагностик компиляторов ================
+
+    // Разбирает сохраненный вывод GCC, Clang или MSVC
+    // local ok, _ = task_run("g++ -c main.cpp", function(line) table.insert(lines, line) end)
+    // local summary = parse_diagnostics(table.concat(lines, "\n"))
+    // for _, d in ipairs(summary.diagnostics) do
+    //     print(d.level, d.file, d.line, d.column, d.message, d.code)
+    // end
+    // for file, counts in pairs(summary.files) do print(file, counts.errors, counts.warnings) end
+    // Сообщения note группируются с предшествующей ошибкой или предупреждением (поле notes)
+    // tool: "gcc" (GCC/Clang), "msvc", "ld" или имя программы для сообщений без файла
+    // ("collect2", "cc1plus", "LINK")
+    // Возвращает: таблица {diagnostics = {{level, fatal, file, line, column, message, code, tool,
+    //             rendered, notes}, ...}, errors, warnings, notes, files = {[file] = {errors, warnings, notes}}}
+    let parse_diagnostics = lua.create_function(|lua, text: String| diagnostics_summary(lua, &text))?;
+    globals.set("parse_diagnostics", parse_diagnostics)?;
+
+    // Создает потоковый разборщик диагностик для task_run
+    // local parser = diagnostic_parser({
+    //     on_diagnostic = function(d) if d.level == "error" then print_error(d.rendered) end end,
+    //     on_line = function(line, stream) println(line) end,
+    // })
+    // task_run("g++ -c main.cpp", parser:callback())
+    // parser:finish()
+    // local summary = parser:result()
+    // on_diagnostic вызывается, когда сообщение полностью получено (вместе с notes);
+    // возврат false или "abort" из on_diagnostic или on_line прерывает команду
+    // Методы: callback(), feed(line), finish(), result()
+    // Возвращает: userdata (разборщик)
+    let diagnostic_parser = lua.create_function(|_, options: Option<mlua::Table>| {
+        let mut parser = DiagnosticParser {
+            state: Arc::new(Mutex::new(ParserState::default())),
+            on_diagnostic: None,
+            on_line: None,
+        };
+        if let Some(options) = options {
+            parser.on_diagnostic = options.get::<Option<mlua::Function>>("on_diagnostic")?;
+            parser.on_line = options.get::<Option<mlua::Function>>("on_line")?;
+        }
+        Ok(parser)
+    })?;
+    globals.set("diagnostic_parser", diagnostic_parser)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_gcc_diagnostic() {
+        let diagnostic = Diagnostic::from_line("src/main.cpp:12:5: warning: unused variable 'x' [-Wunused-variable]").unwrap();
+        assert_eq!(diagnostic.level, "warning");
+        assert!(!diagnostic.fatal);
+        assert_eq!(diagnostic.file.as_deref(), Some("src/main.cpp"));
+        assert_eq!(diagnostic.line, Some(12));
+        assert_eq!(diagnostic.column, Some(5));
+        assert_eq!(diagnostic.message, "unused variable 'x'");
+        assert_eq!(diagnostic.code.as_deref(), Some("-Wunused-variable"));
+        assert_eq!(diagnostic.tool, "gcc");
+    }
+
+    #[test]
+    fn parses_gcc_diagnostic_without_column() {
+        let diagnostic = Diagnostic::from_line("lib.c:3: fatal error: foo.h: No such file or directory").unwrap();
+        assert_eq!(diagnostic.level, "error");
+        assert!(diagnostic.fatal);
+        assert_eq!(diagnostic.line, Some(3));
+        assert_eq!(diagnostic.column, None);
+        assert_eq!(diagnostic.message, "foo.h: No such file or directory");
+    }
+
+    #[test]
+    fn parses_msvc_diagnostic() {
+        let diagnostic = Diagnostic::from_line(r"src\main.cpp(12,5): error C2065: 'x': undeclared identifier").unwrap();
+        assert_eq!(diagnostic.level, "error");
+        assert_eq!(diagnostic.file.as_deref(), Some(r"src\main.cpp"));
+        assert_eq!(diagnostic.line, Some(12));
+        assert_eq!(diagnostic.column, Some(5));
+        assert_eq!(diagnostic.code.as_deref(), Some("C2065"));
+        assert_eq!(diagnostic.message, "'x': undeclared identifier");
+        assert_eq!(diagnostic.tool, "msvc");
+    }
+
+    #[test]
+    fn parses_tool_and_linker_messages() {
+        let diagnostic = Diagnostic::from_line("collect2: error: ld returned 1 exit status").unwrap();
+        assert_eq!(diagnostic.tool, "collect2");
+        assert_eq!(diagnostic.file, None);
+        assert_eq!(diagnostic.message, "ld returned 1 exit status");
+
+        let diagnostic = Diagnostic::from_line("LINK : fatal error LNK1104: cannot open file 'app.exe'").unwrap();
+        assert_eq!(diagnostic.tool, "LINK");
+        assert!(diagnostic.fatal);
+        assert_eq!(diagnostic.code.as_deref(), Some("LNK1104"));
+
+        let diagnostic = Diagnostic::from_line("main.cpp:(.text+0x5): undefined reference to `foo()'").unwrap();
+        assert_eq!(diagnostic.tool, "ld");
+        assert_eq!(diagnostic.level, "error");
+        assert_eq!(diagnostic.file.as_deref(), Some("main.cpp"));
+        assert_eq!(diagnostic.message, "undefined reference to `foo()'");
+    }
+
+    #[test]
+    fn ignores_unrelated_lines() {
+        assert!(Diagnostic::from_line("Compiling main.cpp").is_none());
+        assert!(Diagnostic::from_line("make: *** [all] Error 2").is_none());
+    }
+
+    #[test]
+    fn groups_context_notes_and_source_lines() {
+        let mut state = ParserState::default();
+        let lines = [
+            "main.cpp: In function 'int main()':",
+            "\u{1b}[01mmain.cpp:4:9:\u{1b}[m \u{1b}[01;31merror: \u{1b}[m'y' was not declared in this scope",
+            "    4 |     x = y;",
+            "      |         ^",
+            "main.cpp:2:5: note: 'x' declared here",
+            "main.cpp:9:1: warning: no return statement [-Wreturn-type]",
+        ];
+        let mut completed = Vec::new();
+        for line in lines {
+            completed.extend(state.feed(line));
+        }
+        assert_eq!(completed.len(), 1);
+        let error = &completed[0];
+        assert_eq!(error.level, "error");
+        assert_eq!(error.message, "'y' was not declared in this scope");
+        assert_eq!(error.rendered.len(), 5);
+        assert_eq!(error.rendered[0], "main.cpp: In function 'int main()':");
+        assert_eq!(error.notes.len(), 1);
+        assert_eq!(error.notes[0].line, Some(2));
+
+        let warning = state.finish().unwrap();
+        assert_eq!(warning.level, "warning");
+        assert_eq!(warning.rendered, vec!["main.cpp:9:1: warning: no return statement [-Wreturn-type]"]);
+        assert_eq!(state.diagnostics.len(), 2);
+    }
+
+    #[test]
+    fn unrelated_line_completes_diagnostic() {
+        let mut state = ParserState::default();
+        assert!(state.feed("a.c:1:1: error: expected ';'").is_empty());
+        let completed = state.feed("make: *** [all] Error 1");
+        assert_eq!(completed.len(), 1);
+        assert!(state.finish().is_none());
+    }
+}
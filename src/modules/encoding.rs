@@ -0,0 +1,147 @@
+use encoding_rs::{CoderResult, Decoder, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// Кодировка текста процессов и файлов
+// Поддерживаются метки WHATWG: "utf-8", "cp866", "cp1251", "cp1252", "koi8-r", "koi8-u",
+// "iso-8859-5", "utf-16le", "utf-16be", "utf-16" (= utf-16le) и т.д.
+// "raw" - байты передаются в Lua без преобразования
+#[derive(Clone, Copy)]
+pub(crate) enum TextEncoding {
+    Raw,
+    Text(&'static Encoding),
+}
+
+impl Default for TextEncoding {
+    fn default() -> Self {
+        TextEncoding::Text(UTF_8)
+    }
+}
+
+impl TextEncoding {
+    pub(crate) fn from_label(label: Option<&str>) -> mlua::Result<Self> {
+        let Some(label) = label else {
+            return Ok(Self::default());
+        };
+        let normalized = label.trim().to_lowercase();
+        if normalized == "raw" || normalized == "binary" {
+            return Ok(TextEncoding::Raw);
+        }
+        Encoding::for_label(normalized.as_bytes())
+            .map(TextEncoding::Text)
+            .ok_or_else(|| mlua::Error::external(format!("Unsupported encoding: {}", label)))
+    }
+
+    // Декодирует содержимое целиком в UTF-8 (для raw возвращает байты как есть)
+    pub(crate) fn decode(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            TextEncoding::Raw => bytes.to_vec(),
+            TextEncoding::Text(encoding) => encoding.decode(bytes).0.into_owned().into_bytes(),
+        }
+    }
+
+    // Кодирует UTF-8 текст в выбранную кодировку (для raw возвращает байты как есть)
+    pub(crate) fn encode(&self, bytes: &[u8]) -> Vec<u8> {
+        let TextEncoding::Text(encoding) = self else {
+            return bytes.to_vec();
+        };
+        let text = String::from_utf8_lossy(bytes);
+        // encoding_rs не умеет кодировать в UTF-16, поэтому делаем это вручную
+        if *encoding == UTF_16LE {
+            text.encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect()
+        } else if *encoding == UTF_16BE {
+            text.encode_utf16().flat_map(|unit| unit.to_be_bytes()).collect()
+        } else {
+            encoding.encode(&text).0.into_owned()
+        }
+    }
+}
+
+// Потоковый декодер: преобразует куски вывода в UTF-8, корректно обрабатывая
+// многобайтовые символы, разрезанные между кусками
+pub(crate) struct ChunkDecoder {
+    decoder: Option<Decoder>,
+}
+
+impl ChunkDecoder {
+    pub(crate) fn new(encoding: TextEncoding) -> Self {
+        match encoding {
+            TextEncoding::Raw => Self { decoder: None },
+            TextEncoding::Text(encoding) => Self { decoder: Some(encoding.new_decoder()) },
+        }
+    }
+
+    // Декодирует очередной кусок; last = true для завершающего вызова
+    pub(crate) fn decode(&mut self, chunk: &[u8], last: bool) -> Vec<u8> {
+        let Some(decoder) = self.decoder.as_mut() else {
+            return chunk.to_vec();
+        };
+
+        let capacity = decoder.max_utf8_buffer_length(chunk.len()).unwrap_or(chunk.len() * 3 + 16);
+        let mut output = vec![0u8; capacity];
+        let mut total_read = 0;
+        let mut total_written = 0;
+        loop {
+            let (result, read, written, _) =
+                decoder.decode_to_utf8(&chunk[total_read..], &mut output[total_written..], last);
+            total_read += read;
+            total_written += written;
+            match result {
+                CoderResult::InputEmpty => break,
+                CoderResult::OutputFull => output.resize(output.len() * 2 + 16, 0),
+            }
+        }
+        output.truncate(total_written);
+        output
+    }
+}
+
+// Построчное чтение потока с декодированием
+// Строка возвращается без завершающего \n (и \r перед ним) в виде UTF-8 байт или сырых байт
+pub(crate) struct LineReader<R> {
+    reader: R,
+    decoder: ChunkDecoder,
+    buffer: Vec<u8>,
+    finished: bool,
+}
+
+impl<R: AsyncRead + Unpin> LineReader<R> {
+    pub(crate) fn new(reader: R, encoding: TextEncoding) -> Self {
+        Self {
+            reader,
+            decoder: ChunkDecoder::new(encoding),
+            buffer: Vec::new(),
+            finished: false,
+        }
+    }
+
+    pub(crate) async fn next_line(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+                let mut line = self.buffer.drain(..=pos).collect::<Vec<u8>>();
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return Ok(Some(line));
+            }
+
+            if self.finished {
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                }
+                return Ok(Some(std::mem::take(&mut self.buffer)));
+            }
+
+            let mut chunk = [0u8; 4096];
+            let read = self.reader.read(&mut chunk).await?;
+            if read == 0 {
+                self.finished = true;
+                let rest = self.decoder.decode(&[], true);
+                self.buffer.extend_from_slice(&rest);
+            } else {
+                let decoded = self.decoder.decode(&chunk[..read], false);
+                self.buffer.extend_from_slice(&decoded);
+            }
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use crate::modules::utility::string_list;
+use crate::modules::walk::{gitignore_option, walk_builder};
+use ::glob::{MatchOptions, Pattern};
+use mlua::{Lua, Value};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Параметры поиска файлов по шаблону
+struct GlobOptions {
+    base: PathBuf,
+    exclude: Vec<Pattern>,
+    // Шаблоны исключений вида "target/**" отсекают и саму папку, чтобы не обходить ее содержимое
+    exclude_dirs: Vec<Pattern>,
+    follow_symlinks: bool,
+    hidden: bool,
+    absolute: bool,
+    gitignore: bool,
+}
+
+impl GlobOptions {
+    fn from_table(options: Option<&mlua::Table>, script_dir: &Path) -> mlua::Result<Self> {
+        let mut result = Self {
+            base: script_dir.to_path_buf(),
+            exclude: Vec::new(),
+            exclude_dirs: Vec::new(),
+            follow_symlinks: false,
+            hidden: false,
+            absolute: false,
+            gitignore: false,
+        };
+        let Some(options) = options else {
+            return Ok(result);
+        };
+
+        if let Some(base) = options.get::<Option<String>>("base")? {
+            result.base = script_dir.join(base);
+        }
+        for pattern in string_list(options.get::<Value>("exclude")?, "exclude")?
+            .iter()
+            .flat_map(|pattern| expand_braces(pattern))
+        {
+            if let Some(dir) = pattern.strip_suffix("/**") {
+                result.exclude_dirs.push(compile_pattern(dir)?);
+            }
+            result.exclude.push(compile_pattern(&pattern)?);
+        }
+        result.follow_symlinks = options.get::<Option<bool>>("follow_symlinks")?.unwrap_or(false);
+        result.hidden = options.get::<Option<bool>>("hidden")?.unwrap_or(false);
+        result.absolute = options.get::<Option<bool>>("absolute")?.unwrap_or(false);
+        result.gitignore = gitignore_option(Some(options))?;
+        Ok(result)
+    }
+
+    fn match_options(&self) -> MatchOptions {
+        MatchOptions {
+            case_sensitive: !cfg!(windows),
+            require_literal_separator: true,
+            require_literal_leading_dot: !self.hidden,
+        }
+    }
+
+    fn is_excluded(&self, path: &str, is_dir: bool) -> bool {
+        let options = self.match_options();
+        self.exclude.iter().any(|pattern| pattern.matches_with(path, options))
+            || (is_dir && self.exclude_dirs.iter().any(|pattern| pattern.matches_with(path, options)))
+    }
+}
+
+pub fn setup_globals_glob(
+    lua: Lua,
+    script_path: String,
+    target: &Option<String>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let globals = lua.globals();
+    let script_dir = Path::new(&script_path).parent().unwrap_or(Path::new(".")).to_path_buf();
+
+    // ================ Поиск файлов по шаблону ================
+
+    // Ищет файлы и папки по glob шаблонам
+    // local sources = glob("src/**/*.rs")
+    // local headers = glob({"include/**/*.{h,hpp}", "third_party/*/include/*.h"})
+    // local assets = glob("**/*", {base = "assets", exclude = {"**/*.psd", "raw/**"}})
+    // Шаблоны: * и ? (в пределах одной папки), ** (любое число папок), [abc], {a,b} (варианты)
+    // Опции: exclude (string | table) - шаблоны исключений, как и основные, относительно base
+    //        base (string) - папка, от которой считаются шаблоны (по умолчанию папка скрипта)
+    //        follow_symlinks (boolean, по умолчанию false) - заходить в папки по символическим ссылкам
+    //        hidden (boolean, по умолчанию false) - включать файлы и папки, начинающиеся с точки
+    //        absolute (boolean, по умолчанию false) - возвращать абсолютные пути
+    //        gitignore (boolean, по умолчанию false) - пропускать пути из .gitignore, .ignore и .solverignore
+    // Возвращает: table (отсортированный массив путей относительно папки скрипта;
+    //             пути вне папки скрипта возвращаются абсолютными)
+    let glob = lua.create_async_function(move |_, (patterns, options): (Value, Option<mlua::Table>)| {
+        let script_dir = script_dir.clone();
+        async move {
+            let patterns = match patterns {
+                Value::String(_) | Value::Table(_) => string_list(patterns, "pattern")?,
+                _ => return Err(mlua::Error::external("glob: pattern must be a string or a table of strings")),
+            };
+            let options = Arc::new(GlobOptions::from_table(options.as_ref(), &script_dir)?);
+
+            let paths = tokio::task::spawn_blocking(move || glob_paths(&patterns, &options, &script_dir))
+                .await
+                .map_err(mlua::Error::external)??;
+            Ok(paths)
+        }
+    })?;
+    globals.set("glob", glob)?;
+
+    Ok(())
+}
+
+// Список шаблонов для фильтров include/exclude функций копирования
+// Шаблон без "/" сравнивается с именем файла или папки на любом уровне, остальные - с путем относительно корня
+#[derive(Clone)]
+pub(crate) struct PatternList(Vec<(Pattern, bool)>);
+
+impl PatternList {
+    pub(crate) fn from_value(value: Value, option: &str) -> mlua::Result<Self> {
+        let mut patterns = Vec::new();
+        for pattern in string_list(value, option)?.iter().flat_map(|pattern| expand_braces(pattern)) {
+            let pattern = pattern.replace('\\', "/");
+            patterns.push((compile_pattern(&pattern)?, pattern.contains('/')));
+        }
+        Ok(Self(patterns))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // relative - путь с разделителями "/"
+    pub(crate) fn matches(&self, relative: &str) -> bool {
+        let options = MatchOptions {
+            case_sensitive: !cfg!(windows),
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        self.0.iter().any(|(pattern, full_path)| {
+            pattern.matches_with(if *full_path { relative } else { name }, options)
+        })
+    }
+}
+
+// ================ Вспомогательные функции ================
+
+fn glob_paths(patterns: &[String], options: &Arc<GlobOptions>, script_dir: &Path) -> mlua::Result<Vec<String>> {
+    let match_options = options.match_options();
+    let mut found = BTreeSet::new();
+
+    for pattern in patterns.iter().flat_map(|pattern| expand_braces(pattern)) {
+        let pattern = pattern.replace('\\', "/");
+        let compiled = compile_pattern(&pattern)?;
+        let (root, depth) = literal_root(&pattern);
+        let root = options.base.join(root);
+
+        // Шаблон без спецсимволов - обычный путь
+        let Some(depth) = depth else {
+            let relative = relative_path(&root, &options.base);
+            if root.symlink_metadata().is_ok() && !options.is_excluded(&relative, root.is_dir()) {
+                found.insert(root);
+            }
+            continue;
+        };
+
+        // Скрытые папки обходим, только если шаблон явно их упоминает
+        let prune_hidden = !options.hidden && !pattern.starts_with('.') && !pattern.contains("/.");
+        let filter_options = options.clone();
+        let walker = walk_builder(&root, options.gitignore)
+            .follow_links(options.follow_symlinks)
+            .max_depth(depth)
+            .filter_entry(move |entry| {
+                if entry.depth() == 0 || !is_dir(entry) {
+                    return true;
+                }
+                if prune_hidden && entry.file_name().to_string_lossy().starts_with('.') {
+                    return false;
+                }
+                !filter_options.is_excluded(&relative_path(entry.path(), &filter_options.base), true)
+            })
+            .build();
+
+        // Недоступные папки и битые ссылки пропускаются
+        for entry in walker.flatten().filter(|entry| entry.depth() > 0) {
+            let relative = relative_path(entry.path(), &options.base);
+            if compiled.matches_with(&relative, match_options) && !options.is_excluded(&relative, is_dir(&entry)) {
+                found.insert(entry.into_path());
+            }
+        }
+    }
+
+    Ok(found
+        .into_iter()
+        .map(|path| display_path(&path, script_dir, options.absolute))
+        .collect())
+}
+
+fn is_dir(entry: &ignore::DirEntry) -> bool {
+    entry.file_type().is_some_and(|file_type| file_type.is_dir())
+}
+
+fn compile_pattern(pattern: &str) -> mlua::Result<Pattern> {
+    Pattern::new(pattern).map_err(|e| mlua::Error::external(format!("Invalid glob pattern '{}': {}", pattern, e)))
+}
+
+// Раскрывает фигурные скобки: "src/{a,b}/*.{c,h}" -> 4 шаблона (вложенные скобки тоже раскрываются)
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut depth = 0;
+    let mut open = 0;
+    for (index, &c) in chars.iter().enumerate() {
+        match c {
+            '{' => {
+                if depth == 0 {
+                    open = index;
+                }
+                depth += 1;
+            }
+            '}' if depth > 0 => {
+                depth -= 1;
+                if depth > 0 {
+                    continue;
+                }
+                let inner: String = chars[open + 1..index].iter().collect();
+                let alternatives = split_alternatives(&inner);
+                // {abc} без запятых остается как есть
+                if alternatives.len() < 2 {
+                    continue;
+                }
+                let prefix: String = chars[..open].iter().collect();
+                let suffix: String = chars[index + 1..].iter().collect();
+                return alternatives
+                    .iter()
+                    .flat_map(|alternative| expand_braces(&format!("{}{}{}", prefix, alternative, suffix)))
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+    vec![pattern.to_string()]
+}
+
+// Делит содержимое скобок по запятым верхнего уровня
+fn split_alternatives(inner: &str) -> Vec<String> {
+    let mut alternatives = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    for c in inner.chars() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                alternatives.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    alternatives.push(current);
+    alternatives
+}
+
+// Начальная часть шаблона без спецсимволов, с которой начинается обход, и глубина обхода
+// Возвращает: (папка, None - в шаблоне нет спецсимволов | Some(None) - есть ** | Some(Some(n)) - n уровней)
+fn literal_root(pattern: &str) -> (PathBuf, Option<Option<usize>>) {
+    let is_special = |component: &str| component.contains(['*', '?', '[']);
+    let components: Vec<&str> = pattern.split('/').collect();
+    let literal = components.iter().take_while(|component| !is_special(component)).count();
+    if literal == components.len() {
+        return (PathBuf::from(pattern), None);
+    }
+
+    let mut root = components[..literal].join("/");
+    if root.is_empty() && pattern.starts_with('/') {
+        root.push('/');
+    }
+    let rest = &components[literal..];
+    let depth = if rest.iter().any(|component| component.contains("**")) {
+        None
+    } else {
+        Some(rest.len())
+    };
+    (PathBuf::from(root), Some(depth))
+}
+
+// Путь относительно base с разделителями "/" для сопоставления с шаблонами
+pub(crate) fn relative_path(path: &Path, base: &Path) -> String {
+    let relative = path.strip_prefix(base).unwrap_or(path);
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+fn display_path(path: &Path, script_dir: &Path, absolute: bool) -> String {
+    let path = match path.strip_prefix(script_dir) {
+        Ok(relative) if !absolute => relative,
+        _ => path,
+    };
+    let path = path.to_string_lossy();
+    path.strip_prefix(r"\\?\").unwrap_or(&path).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(list: &[&str]) -> PatternList {
+        let lua = Lua::new();
+        let table = lua.create_sequence_from(list.iter().copied()).unwrap();
+        PatternList::from_value(Value::Table(table), "include").unwrap()
+    }
+
+    #[test]
+    fn expands_braces() {
+        assert_eq!(expand_braces("*.{c,h}"), vec!["*.c", "*.h"]);
+        assert_eq!(
+            expand_braces("src/{a,b}/*.{c,h}"),
+            vec!["src/a/*.c", "src/a/*.h", "src/b/*.c", "src/b/*.h"]
+        );
+        assert_eq!(expand_braces("{a,b{1,2}}.txt"), vec!["a.txt", "b1.txt", "b2.txt"]);
+        assert_eq!(expand_braces("x{,.bak}"), vec!["x", "x.bak"]);
+    }
+
+    #[test]
+    fn keeps_braces_without_alternatives() {
+        assert_eq!(expand_braces("{abc}.txt"), vec!["{abc}.txt"]);
+        assert_eq!(expand_braces("a{b,c"), vec!["a{b,c"]);
+        assert_eq!(expand_braces("plain/*.rs"), vec!["plain/*.rs"]);
+    }
+
+    #[test]
+    fn finds_literal_root_and_depth() {
+        assert_eq!(literal_root("src/*.rs"), (PathBuf::from("src"), Some(Some(1))));
+        assert_eq!(literal_root("src/**/*.rs"), (PathBuf::from("src"), Some(None)));
+        assert_eq!(literal_root("*/mod.rs"), (PathBuf::from(""), Some(Some(2))));
+        assert_eq!(literal_root("/etc/*.conf"), (PathBuf::from("/etc"), Some(Some(1))));
+        assert_eq!(literal_root("Cargo.toml"), (PathBuf::from("Cargo.toml"), None));
+    }
+
+    #[test]
+    fn pattern_without_slash_matches_name_at_any_level() {
+        let list = patterns(&["*.{png,json}", "node_modules"]);
+        assert!(list.matches("logo.png"));
+        assert!(list.matches("assets/icons/logo.png"));
+        assert!(list.matches("web/node_modules"));
+        assert!(list.matches("data.json"));
+        assert!(!list.matches("logo.png.bak"));
+        assert!(!list.matches("assets/style.css"));
+    }
+
+    #[test]
+    fn pattern_with_slash_matches_relative_path() {
+        let list = patterns(&["raw/*.psd", "**/generated/**"]);
+        assert!(list.matches("raw/cover.psd"));
+        assert!(!list.matches("art/raw/cover.psd"));
+        assert!(!list.matches("raw/sub/cover.psd"));
+        assert!(list.matches("src/generated/api.rs"));
+        assert!(list.matches("generated/deep/x.rs"));
+    }
+
+    #[test]
+    fn backslashes_in_patterns_are_separators() {
+        let list = patterns(&[r"raw\*.psd"]);
+        assert!(list.matches("raw/cover.psd"));
+        assert!(!patterns(&[]).matches("anything"));
+        assert!(patterns(&[]).is_empty());
+    }
+}
@@ -0,0 +1,446 @@
+use crate::modules::copy::{CopyFilter, EntryKind, collect_entries};
+use mlua::{Lua, Value};
+use sha2::Digest;
+use std::fmt::Write as _;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Поддерживаемые алгоритмы хеширования
+#[derive(Clone, Copy)]
+enum Algorithm {
+    Sha256,
+    Sha1,
+    Md5,
+    Blake3,
+}
+
+enum Hasher {
+    Sha256(sha2::Sha256),
+    Sha1(sha1::Sha1),
+    Md5(md5::Md5),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Algorithm {
+    fn from_name(name: Option<&str>) -> mlua::Result<Self> {
+        match name.map(|name| name.to_ascii_lowercase()).as_deref() {
+            None | Some("sha256") => Ok(Self::Sha256),
+            Some("sha1") => Ok(Self::Sha1),
+            Some("md5") => Ok(Self::Md5),
+            Some("blake3") => Ok(Self::Blake3),
+            Some(other) => Err(mlua::Error::external(format!(
+                "Unknown hash algorithm '{}' (expected sha256, sha1, md5 or blake3)",
+                other
+            ))),
+        }
+    }
+
+    // Алгоритм по длине хеша в манифесте (blake3 и sha256 одной длины, поэтому blake3 только явно)
+    fn from_hex_length(length: usize) -> Option<Self> {
+        match length {
+            64 => Some(Self::Sha256),
+            40 => Some(Self::Sha1),
+            32 => Some(Self::Md5),
+            _ => None,
+        }
+    }
+
+    fn hasher(self) -> Hasher {
+        match self {
+            Self::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            Self::Sha1 => Hasher::Sha1(sha1::Sha1::new()),
+            Self::Md5 => Hasher::Md5(md5::Md5::new()),
+            Self::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(data),
+            Self::Sha1(hasher) => hasher.update(data),
+            Self::Md5(hasher) => hasher.update(data),
+            Self::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    fn finalize_hex(self) -> String {
+        let bytes = match self {
+            Self::Sha256(hasher) => hasher.finalize().to_vec(),
+            Self::Sha1(hasher) => hasher.finalize().to_vec(),
+            Self::Md5(hasher) => hasher.finalize().to_vec(),
+            Self::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+        };
+        bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+    }
+}
+
+pub fn setup_globals_hash(
+    lua: Lua,
+    script_path: String,
+    target: &Option<String>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let globals = lua.globals();
+
+    // ================ Хеширование файлов ================
+
+    // Вычисляет хеш содержимого файла
+    // local sum = hash_file("dist/app.zip")             -- sha256
+    // local b3 = hash_file("dist/app.zip", "blake3")
+    // Алгоритмы: "sha256" (по умолчанию), "sha1", "md5", "blake3"
+    // Возвращает: string | nil (хеш в нижнем регистре hex или nil при ошибке чтения)
+    let hash_file = lua.create_async_function(|_, (path, algorithm): (String, Option<String>)| async move {
+        let algorithm = Algorithm::from_name(algorithm.as_deref())?;
+        let hash = tokio::task::spawn_blocking(move || hash_path(Path::new(&path), algorithm))
+            .await
+            .map_err(mlua::Error::external)?;
+        Ok(hash.ok())
+    })?;
+    globals.set("hash_file", hash_file)?;
+
+    // Вычисляет хеш дерева файлов: не зависит от ОС, порядка обхода и времени изменения
+    // local tree = hash_dir("src")
+    // local assets = hash_dir("assets", {algo = "blake3", exclude = "*.tmp", gitignore = true})
+    // Хешируется отсортированный список строк "<хеш файла>  <путь относительно папки>"
+    // (как в манифесте sha256sum), поэтому учитываются и содержимое, и имена файлов
+    // Опции: algo (string) - алгоритм, как у hash_file
+    //        include, exclude, include_dirs, exclude_dirs, symlinks, gitignore - как у copy_dir
+    // Возвращает: string | nil (хеш или nil, если папку не удалось прочитать)
+    let hash_dir = lua.create_async_function(|_, (path, options): (String, Option<mlua::Table>)| async move {
+        let algorithm = algorithm_option(options.as_ref())?;
+        let filter = Arc::new(CopyFilter::from_table(options.as_ref())?);
+        let Ok(files) = hash_tree(Path::new(&path), filter, algorithm).await else {
+            return Ok(None);
+        };
+        let mut hasher = algorithm.hasher();
+        for (relative, hash) in files {
+            hasher.update(manifest_line(&hash, &relative).as_bytes());
+        }
+        Ok(Some(hasher.finalize_hex()))
+    })?;
+    globals.set("hash_dir", hash_dir)?;
+
+    // ================ Манифесты контрольных сумм ================
+
+    // Записывает манифест в формате sha256sum ("<хеш>  <путь>" в каждой строке)
+    // write_checksums("dist/SHA256SUMS", {"dist/app.zip", "dist/app.tar.gz"})
+    // write_checksums("dist/SHA256SUMS", "dist")   -- все файлы папки, кроме самого манифеста
+    // write_checksums("dist/MD5SUMS", files, {algo = "md5"})
+    // Пути в манифесте записываются относительно его папки (файлы вне нее - через ".."),
+    // поэтому "sha256sum -c SHA256SUMS", запущенный в этой папке, и verify_checksums находят файлы
+    // Опции: algo (string) - алгоритм, как у hash_file
+    //        include, exclude, include_dirs, exclude_dirs, symlinks, gitignore - для папки, как у copy_dir
+    // Возвращает: boolean, string? (true или false и сообщение об ошибке)
+    let write_checksums = lua.create_async_function(
+        |_, (manifest, files, options): (String, Value, Option<mlua::Table>)| async move {
+            let algorithm = algorithm_option(options.as_ref())?;
+            let manifest = PathBuf::from(manifest);
+            // Канонические пути, чтобы вычислить путь файла относительно папки манифеста
+            let canonical_manifest = match canonical_file(&manifest) {
+                Ok(path) => path,
+                Err(e) => return Ok((false, Some(format!("{}: {}", manifest.display(), e)))),
+            };
+            let manifest_dir = canonical_manifest.parent().unwrap_or(Path::new("")).to_path_buf();
+
+            let entries: Vec<(String, String)> = match files {
+                // Папка: все файлы, прошедшие фильтры, кроме самого манифеста
+                Value::String(dir) => {
+                    let dir = PathBuf::from(dir.to_str()?.to_string());
+                    let filter = Arc::new(CopyFilter::from_table(options.as_ref())?);
+                    let files = match (std::fs::canonicalize(&dir), hash_tree(&dir, filter, algorithm).await) {
+                        (Ok(canonical_dir), Ok(files)) => files
+                            .into_iter()
+                            .map(|(relative, hash)| (canonical_dir.join(relative), hash))
+                            .filter(|(file, _)| *file != canonical_manifest)
+                            .collect::<Vec<_>>(),
+                        (Err(e), _) | (_, Err(e)) => return Ok((false, Some(format!("{}: {}", dir.display(), e)))),
+                    };
+                    files.into_iter().map(|(file, hash)| (manifest_path(&file, &manifest_dir), hash)).collect()
+                }
+                Value::Table(files) => {
+                    let files = files.sequence_values::<String>().collect::<mlua::Result<Vec<_>>>()?;
+                    let mut entries = Vec::new();
+                    for file in files {
+                        let file = PathBuf::from(file);
+                        let path = file.clone();
+                        let hash = tokio::task::spawn_blocking(move || {
+                            Ok::<_, std::io::Error>((hash_path(&path, algorithm)?, canonical_file(&path)?))
+                        })
+                        .await
+                        .map_err(mlua::Error::external)?;
+                        match hash {
+                            Ok((hash, canonical)) => entries.push((manifest_path(&canonical, &manifest_dir), hash)),
+                            Err(e) => return Ok((false, Some(format!("{}: {}", file.display(), e)))),
+                        }
+                    }
+                    entries
+                }
+                _ => return Err(mlua::Error::external("write_checksums: files must be a directory path or a table of paths")),
+            };
+
+            let content: String = entries.iter().map(|(path, hash)| manifest_line(hash, path)).collect();
+            match tokio::fs::write(&manifest, content).await {
+                Ok(_) => Ok((true, None)),
+                Err(e) => Ok((false, Some(format!("{}: {}", manifest.display(), e)))),
+            }
+        },
+    )?;
+    globals.set("write_checksums", write_checksums)?;
+
+    // Проверяет файлы по манифесту в формате sha256sum (как "sha256sum -c")
+    // local ok, report = verify_checksums("downloads/SHA256SUMS")
+    // if not ok then
+    //     for _, path in ipairs(report.failed) do print_error("checksum mismatch: " .. path) end
+    // end
+    // Пути в манифесте считаются относительно его папки; алгоритм определяется по длине хеша
+    // (md5, sha1, sha256), blake3 нужно указать явно
+    // Опции: algo (string) - алгоритм, как у hash_file
+    // Возвращает: boolean (все файлы совпали), table {ok, failed, missing} | nil, string (манифест не прочитан)
+    let verify_checksums = lua.create_async_function(|lua, (manifest, options): (String, Option<mlua::Table>)| async move {
+        let algorithm = match &options {
+            Some(options) => options.get::<Option<String>>("algo")?,
+            None => None,
+        };
+        let algorithm = algorithm.map(|name| Algorithm::from_name(Some(&name))).transpose()?;
+        let manifest = PathBuf::from(manifest);
+        let content = match tokio::fs::read_to_string(&manifest).await {
+            Ok(content) => content,
+            Err(e) => return Ok((Value::Nil, Value::String(lua.create_string(format!("{}: {}", manifest.display(), e))?))),
+        };
+        let manifest_dir = manifest.parent().unwrap_or(Path::new("")).to_path_buf();
+
+        let (ok, failed, missing) = (lua.create_table()?, lua.create_table()?, lua.create_table()?);
+        for (number, line) in content.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((expected, path)) = parse_manifest_line(line) else {
+                return Err(mlua::Error::external(format!(
+                    "{}:{}: invalid checksum line",
+                    manifest.display(),
+                    number + 1
+                )));
+            };
+            let Some(algorithm) = algorithm.or_else(|| Algorithm::from_hex_length(expected.len())) else {
+                return Err(mlua::Error::external(format!(
+                    "{}:{}: cannot detect hash algorithm, pass {{algo = ...}}",
+                    manifest.display(),
+                    number + 1
+                )));
+            };
+
+            let file = manifest_dir.join(&path);
+            let hash = tokio::task::spawn_blocking(move || hash_path(&file, algorithm))
+                .await
+                .map_err(mlua::Error::external)?;
+            match hash {
+                Ok(hash) if hash.eq_ignore_ascii_case(&expected) => ok.push(path)?,
+                Ok(_) => failed.push(path)?,
+                Err(_) => missing.push(path)?,
+            }
+        }
+
+        let all_ok = failed.raw_len() == 0 && missing.raw_len() == 0;
+        let report = lua.create_table()?;
+        report.set("ok", ok)?;
+        report.set("failed", failed)?;
+        report.set("missing", missing)?;
+        Ok((Value::Boolean(all_ok), Value::Table(report)))
+    })?;
+    globals.set("verify_checksums", verify_checksums)?;
+
+    Ok(())
+}
+
+// ================ Вспомогательные функции ================
+
+fn algorithm_option(options: Option<&mlua::Table>) -> mlua::Result<Algorithm> {
+    match options {
+        Some(options) => Algorithm::from_name(options.get::<Option<String>>("algo")?.as_deref()),
+        None => Ok(Algorithm::Sha256),
+    }
+}
+
+// Хеш содержимого файла, читаемого блоками
+fn hash_path(path: &Path, algorithm: Algorithm) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = algorithm.hasher();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hasher.finalize_hex());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+// Хеши файлов папки, отсортированные по относительному пути
+// Символическая ссылка (symlinks = "copy") хешируется по пути, на который она указывает
+async fn hash_tree(dir: &Path, filter: Arc<CopyFilter>, algorithm: Algorithm) -> std::io::Result<Vec<(String, String)>> {
+    let entries = collect_entries(dir, filter).await?;
+    tokio::task::spawn_blocking(move || {
+        let mut files = Vec::new();
+        for entry in entries {
+            let hash = match entry.kind {
+                EntryKind::Dir => continue,
+                EntryKind::File => hash_path(&entry.source, algorithm)?,
+                EntryKind::Symlink => {
+                    let link = std::fs::read_link(&entry.source)?;
+                    let mut hasher = algorithm.hasher();
+                    hasher.update(link.to_string_lossy().replace('\\', "/").as_bytes());
+                    hasher.finalize_hex()
+                }
+            };
+            files.push((entry.relative, hash));
+        }
+        files.sort();
+        Ok(files)
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+// Абсолютный путь файла с разрешенными ссылками в папках; сама ссылка (symlinks = "copy")
+// не разрешается, чтобы путь в манифесте указывал на нее
+fn canonical_file(file: &Path) -> std::io::Result<PathBuf> {
+    let name = file
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a file path"))?;
+    let parent = match file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Ok(std::fs::canonicalize(parent)?.join(name))
+}
+
+// Путь файла для манифеста: относительно папки манифеста (с ".." для файлов вне нее), с разделителями "/"
+// Оба пути должны быть каноническими; файл на другом диске записывается абсолютным путем
+fn manifest_path(file: &Path, manifest_dir: &Path) -> String {
+    let common = file
+        .components()
+        .zip(manifest_dir.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return file.to_string_lossy().replace('\\', "/");
+    }
+    let mut relative = PathBuf::new();
+    for _ in manifest_dir.components().skip(common) {
+        relative.push("..");
+    }
+    for component in file.components().skip(common) {
+        relative.push(component);
+    }
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+// Строка манифеста; имена с "\" или переводом строки экранируются, как это делает sha256sum
+fn manifest_line(hash: &str, path: &str) -> String {
+    if path.contains(['\\', '\n']) {
+        format!("\\{}  {}\n", hash, path.replace('\\', "\\\\").replace('\n', "\\n"))
+    } else {
+        format!("{}  {}\n", hash, path)
+    }
+}
+
+// Разбирает "<хеш>  <путь>" (текстовый режим) или "<хеш> *<путь>" (двоичный режим)
+fn parse_manifest_line(line: &str) -> Option<(String, String)> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+    let (hash, rest) = line.split_once(' ')?;
+    if hash.is_empty() || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let path = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*'))?;
+    let path = path.strip_suffix('\r').unwrap_or(path);
+    if path.is_empty() {
+        return None;
+    }
+    let path = if escaped { unescape(path) } else { path.to_string() };
+    Some((hash.to_string(), path))
+}
+
+fn unescape(path: &str) -> String {
+    let mut result = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn writes_plain_and_escaped_lines() {
+        assert_eq!(manifest_line(HASH, "dist/app.zip"), format!("{}  dist/app.zip\n", HASH));
+        assert_eq!(manifest_line(HASH, "a\\b\nc"), format!("\\{}  a\\\\b\\nc\n", HASH));
+    }
+
+    #[test]
+    fn parses_text_and_binary_lines() {
+        let line = format!("{}  dist/app.zip", HASH);
+        assert_eq!(parse_manifest_line(&line), Some((HASH.to_string(), "dist/app.zip".to_string())));
+        let line = format!("{} *app with spaces.bin\r", HASH);
+        assert_eq!(parse_manifest_line(&line), Some((HASH.to_string(), "app with spaces.bin".to_string())));
+    }
+
+    #[test]
+    fn escaped_lines_round_trip() {
+        for path in ["a\\b", "line\nbreak", "tail\\", "plain"] {
+            let line = manifest_line(HASH, path);
+            let parsed = parse_manifest_line(line.strip_suffix('\n').unwrap());
+            assert_eq!(parsed, Some((HASH.to_string(), path.to_string())), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        assert_eq!(parse_manifest_line("not a checksum line"), None);
+        assert_eq!(parse_manifest_line(HASH), None);
+        assert_eq!(parse_manifest_line(&format!("{} app.zip", HASH)), None);
+        assert_eq!(parse_manifest_line(&format!("{}  ", HASH)), None);
+    }
+
+    #[test]
+    fn detects_algorithm_by_hash_length() {
+        assert!(matches!(Algorithm::from_hex_length(32), Some(Algorithm::Md5)));
+        assert!(matches!(Algorithm::from_hex_length(40), Some(Algorithm::Sha1)));
+        assert!(matches!(Algorithm::from_hex_length(64), Some(Algorithm::Sha256)));
+        assert!(Algorithm::from_hex_length(12).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn manifest_paths_are_relative_to_manifest_dir() {
+        let dir = Path::new("/work/dist");
+        assert_eq!(manifest_path(Path::new("/work/dist/app.zip"), dir), "app.zip");
+        assert_eq!(manifest_path(Path::new("/work/dist/sub/a.txt"), dir), "sub/a.txt");
+        assert_eq!(manifest_path(Path::new("/work/out/app.zip"), dir), "../out/app.zip");
+        assert_eq!(manifest_path(Path::new("/other/app.zip"), dir), "../../other/app.zip");
+        assert_eq!(manifest_path(Path::new("/work/dist/app.zip"), Path::new("/work/dist/a/b")), "../../app.zip");
+    }
+}
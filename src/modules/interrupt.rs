@@ -0,0 +1,300 @@
+use colored::Colorize;
+use mlua::Lua;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::process::Child;
+use tokio::sync::watch;
+
+// Код выхода при прерывании по Ctrl-C (128 + SIGINT, как у shell)
+pub const INTERRUPT_EXIT_CODE: i32 = 130;
+
+// Сколько по умолчанию ждать завершения дочерних процессов и on_interrupt обработчиков
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+// Выставляется обработчиком сигнала при первом Ctrl-C
+static INTERRUPTED: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::Sender::new(false));
+
+// Дочерние процессы всех модулей: PID и признак лидера группы процессов
+// Записи не удаляются при завершении процесса: перед отправкой сигнала проверяется,
+// что PID все еще принадлежит нашему незавершенному потомку
+static CHILDREN: Mutex<Vec<(u32, bool)>> = Mutex::new(Vec::new());
+
+// Обработчики on_interrupt из скрипта
+pub struct InterruptHandlers {
+    callbacks: Vec<mlua::Function>,
+    grace_period: Duration,
+}
+
+// Ошибка выполнения скрипта, прерванного по Ctrl-C
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+pub fn setup_globals_interrupt(
+    lua: Lua,
+    script_path: String,
+    target: &Option<String>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let globals = lua.globals();
+    lua.set_app_data(InterruptHandlers {
+        callbacks: Vec::new(),
+        grace_period: DEFAULT_GRACE_PERIOD,
+    });
+
+    // ================ Прерывание по Ctrl-C ================
+
+    // Регистрирует обработчик, который вызывается при Ctrl-C перед завершением скрипта
+    // on_interrupt(function()
+    //     task_run("docker compose down", println)
+    //     remove_dir("build/tmp")
+    // end, {grace = 10})
+    // При Ctrl-C дочерние процессы получают SIGTERM, затем вызываются обработчики (в порядке регистрации),
+    // и Solver ждет завершения процессов не дольше grace секунд (по умолчанию 5), после чего
+    // принудительно их останавливает и выходит с кодом 130. Повторный Ctrl-C завершает Solver сразу
+    // Опции: grace (number) - период ожидания в секундах
+    let on_interrupt = lua.create_function(|lua, (callback, options): (mlua::Function, Option<mlua::Table>)| {
+        let grace = match &options {
+            Some(options) => options.get::<Option<f64>>("grace")?,
+            None => None,
+        };
+        let grace = grace
+            .map(|grace| {
+                Duration::try_from_secs_f64(grace)
+                    .map_err(|_| mlua::Error::external(format!("on_interrupt: invalid grace period {}", grace)))
+            })
+            .transpose()?;
+
+        if let Some(mut handlers) = lua.app_data_mut::<InterruptHandlers>() {
+            handlers.callbacks.push(callback);
+            if let Some(grace) = grace {
+                handlers.grace_period = grace;
+            }
+        }
+        Ok(())
+    })?;
+    globals.set("on_interrupt", on_interrupt)?;
+
+    Ok(())
+}
+
+// Перехватывает Ctrl-C: первое нажатие запускает корректное завершение скрипта,
+// второе немедленно останавливает дочерние процессы и завершает Solver
+pub fn install_interrupt_handler() {
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        INTERRUPTED.send_replace(true);
+
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        eprintln!("{}", "Interrupted again, exiting immediately".red());
+        signal_children(Signal::Kill);
+        std::process::exit(INTERRUPT_EXIT_CODE);
+    });
+}
+
+// Ожидает первого Ctrl-C
+pub async fn wait_for_interrupt() {
+    let mut interrupted = INTERRUPTED.subscribe();
+    let _ = interrupted.wait_for(|interrupted| *interrupted).await;
+}
+
+// Запоминает дочерний процесс, чтобы при Ctrl-C передать ему сигнал завершения
+// group - процесс является лидером своей группы (spawn, PTY), сигнал получит вся группа
+pub(crate) fn register_child(child: &Child, group: bool) {
+    let Some(pid) = child.id() else {
+        return;
+    };
+    let mut children = CHILDREN.lock().unwrap();
+    children.retain(|&(pid, _)| is_running_child(pid));
+    children.push((pid, group));
+}
+
+// Корректно завершает прерванный скрипт: останавливает дочерние процессы,
+// вызывает on_interrupt обработчики и ждет не дольше grace периода
+// Возвращает: ошибку Interrupted для завершения скрипта
+pub async fn handle_interrupt(lua: &Lua, verbose: bool) -> anyhow::Error {
+    let (callbacks, grace_period) = match lua.app_data_ref::<InterruptHandlers>() {
+        Some(handlers) => (handlers.callbacks.clone(), handlers.grace_period),
+        None => (Vec::new(), DEFAULT_GRACE_PERIOD),
+    };
+    let deadline = Instant::now() + grace_period;
+
+    eprintln!("{}", "Interrupted, stopping child processes (press Ctrl-C again to force exit)".yellow());
+    let signaled = signal_children(Signal::Terminate);
+    if verbose && signaled > 0 {
+        println!("Sent termination signal to {} child process(es)", signaled);
+    }
+
+    for callback in callbacks {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match tokio::time::timeout(remaining, callback.call_async::<()>(())).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => eprintln!("{} {}", "on_interrupt handler failed:".red(), e),
+            Err(_) => {
+                eprintln!("{}", "on_interrupt handler did not finish within the grace period".red());
+                break;
+            }
+        }
+    }
+
+    while running_children() > 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let killed = signal_children(Signal::Kill);
+    if killed > 0 {
+        eprintln!("{}", format!("Killed {} child process(es) after the grace period", killed).red());
+    }
+
+    anyhow::Error::new(Interrupted)
+}
+
+// ================ Вспомогательные функции ================
+
+#[derive(Clone, Copy)]
+enum Signal {
+    Terminate,
+    Kill,
+}
+
+// Отправляет сигнал всем работающим дочерним процессам
+// Возвращает: число процессов, которым отправлен сигнал
+#[cfg(unix)]
+fn signal_children(signal: Signal) -> usize {
+    let signal = match signal {
+        Signal::Terminate => libc::SIGTERM,
+        Signal::Kill => libc::SIGKILL,
+    };
+    let children = CHILDREN.lock().unwrap().clone();
+    children
+        .into_iter()
+        .filter(|&(pid, _)| is_running_child(pid))
+        .filter(|&(pid, group)| signal_child(pid, group, signal))
+        .count()
+}
+
+// Отправляет сигнал процессу вместе с его группой или потомками
+// Возвращает: true, если сигнал доставлен самому процессу
+#[cfg(unix)]
+fn signal_child(pid: u32, group: bool, signal: libc::c_int) -> bool {
+    if group {
+        return unsafe { libc::kill(-(pid as i32), signal) } == 0;
+    }
+    // Потомков ищем до отправки сигнала: после завершения shell они перейдут к init
+    // Найденные процессы останавливаются, пока поиск не перестанет находить новых,
+    // иначе shell успел бы запустить следующую команду между поиском и сигналом
+    let mut stopped = vec![pid];
+    unsafe { libc::kill(pid as i32, libc::SIGSTOP) };
+    loop {
+        let found: Vec<u32> = descendants(pid).into_iter().filter(|pid| !stopped.contains(pid)).collect();
+        if found.is_empty() {
+            break;
+        }
+        for &descendant in &found {
+            unsafe { libc::kill(descendant as i32, libc::SIGSTOP) };
+        }
+        stopped.extend(found);
+    }
+    let signaled = unsafe { libc::kill(pid as i32, signal) } == 0;
+    for &descendant in &stopped[1..] {
+        unsafe { libc::kill(descendant as i32, signal) };
+    }
+    // Остановленный процесс получит SIGTERM только после SIGCONT
+    for &process in &stopped {
+        unsafe { libc::kill(process as i32, libc::SIGCONT) };
+    }
+    signaled
+}
+
+// Принудительно завершает дочерний процесс вместе с запущенными им командами
+// (kill у tokio останавливает только сам процесс, например sh из sh -c)
+// group - как у register_child
+pub(crate) fn kill_child(child: &mut Child, group: bool) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id()
+        && is_running_child(pid)
+    {
+        signal_child(pid, group, libc::SIGKILL);
+        return;
+    }
+    let _ = group;
+    let _ = child.start_kill();
+}
+
+// Все потомки процесса по /proc/<pid>/stat, чтобы сигнал дошел до команд, запущенных из sh -c
+#[cfg(target_os = "linux")]
+fn descendants(root: u32) -> Vec<u32> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let parents: Vec<(u32, u32)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+            let stat = std::fs::read_to_string(entry.path().join("stat")).ok()?;
+            // Имя процесса в скобках может содержать пробелы, поля считаем после ')'
+            let ppid = stat.rsplit_once(')')?.1.split_whitespace().nth(1)?.parse::<u32>().ok()?;
+            Some((pid, ppid))
+        })
+        .collect();
+
+    let mut result = Vec::new();
+    let mut queue = vec![root];
+    while let Some(parent) = queue.pop() {
+        for &(pid, ppid) in &parents {
+            if ppid == parent && !result.contains(&pid) {
+                result.push(pid);
+                queue.push(pid);
+            }
+        }
+    }
+    result
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn descendants(_root: u32) -> Vec<u32> {
+    Vec::new()
+}
+
+// На Windows Ctrl-C и так получают все процессы консоли, а оставшиеся
+// процессы останавливает kill_on_drop при завершении скрипта
+#[cfg(not(unix))]
+fn signal_children(_signal: Signal) -> usize {
+    0
+}
+
+fn running_children() -> usize {
+    let children = CHILDREN.lock().unwrap().clone();
+    children.into_iter().filter(|&(pid, _)| is_running_child(pid)).count()
+}
+
+// PID принадлежит нашему потомку, который еще не завершился
+// (WNOWAIT не забирает код возврата, его по-прежнему получит tokio)
+#[cfg(unix)]
+pub(crate) fn is_running_child(pid: u32) -> bool {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let result = unsafe {
+        libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            &mut info,
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+        )
+    };
+    result == 0 && unsafe { info.si_pid() } == 0
+}
+
+#[cfg(not(unix))]
+fn is_running_child(_pid: u32) -> bool {
+    false
+}
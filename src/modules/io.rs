@@ -1,3 +1,6 @@
+use crate::modules::encoding::TextEncoding;
+use crate::modules::metadata::file_type_name;
+use crate::modules::walk::{IgnoreFilter, gitignore_option};
 use mlua::Lua;
 use std::path::Path;
 
@@ -34,10 +37,27 @@ pub fn setup_globals_io(
 
     // Читает содержимое файла как строку
     // local content = read_file("Cargo.toml")
+    // local log = read_file("build.log", {encoding = "cp1251"})
+    // local data = read_file("image.png", {encoding = "raw"})
+    // Опции: encoding (string) - кодировка файла: "cp866", "cp1251", "utf-16le" и др.,
+    //        "raw" - содержимое возвращается как есть, без проверки UTF-8
+    // Без опции encoding файл должен быть в UTF-8
     // Возвращает: string | nil (содержимое файла или nil при ошибке)
-    let read_file = lua.create_async_function(|_, path: String| async move {
-        match tokio::fs::read_to_string(path).await {
-            Ok(content) => Ok(Some(content)),
+    let read_file = lua.create_async_function(|lua, (path, options): (String, Option<mlua::Table>)| async move {
+        let encoding = match &options {
+            Some(options) => options.get::<Option<String>>("encoding")?,
+            None => None,
+        };
+        let Some(encoding) = encoding else {
+            return match tokio::fs::read_to_string(path).await {
+                Ok(content) => Ok(Some(lua.create_string(content)?)),
+                Err(_) => Ok(None),
+            };
+        };
+
+        let encoding = TextEncoding::from_label(Some(&encoding))?;
+        match tokio::fs::read(path).await {
+            Ok(content) => Ok(Some(lua.create_string(encoding.decode(&content))?)),
             Err(_) => Ok(None),
         }
     })?;
@@ -45,15 +65,58 @@ pub fn setup_globals_io(
 
     // Записывает строку в файл
     // write_file("output.txt", "Hello, World!")
+    // write_file("run.bat", "@echo off\r\necho Привет\r\n", {encoding = "cp866"})
+    // write_file("config.json", json, {atomic = true, create_dirs = true})
+    // write_file("build.log", line .. "\n", {append = true})
+    // write_file("deploy.sh", script, {mode = "755"})
+    // Опции: encoding (string) - кодировка, в которую преобразуется UTF-8 строка,
+    //        "raw" (по умолчанию) - строка записывается как есть
+    //        atomic (boolean) - писать во временный файл рядом и заменять им файл, чтобы при сбое
+    //        не остался наполовину записанный файл (права существующего файла сохраняются)
+    //        append (boolean) - дописывать в конец файла (несовместимо с atomic)
+    //        mode (string | number) - права доступа на Unix: "644", "0o755" или число (420)
+    //        create_dirs (boolean) - создать недостающие родительские папки
     // Возвращает: boolean (true если успешно записан)
-    let write_file = lua.create_async_function(|_, (path, content): (String, String)| async move {
-        match tokio::fs::write(path, content).await {
+    let write_file = lua.create_async_function(|_, (path, content, options): (String, mlua::String, Option<mlua::Table>)| async move {
+        let encoding = match options.as_ref().map(|o| o.get::<Option<String>>("encoding")).transpose()?.flatten() {
+            Some(encoding) => TextEncoding::from_label(Some(&encoding))?,
+            None => TextEncoding::Raw,
+        };
+        let write_options = WriteFileOptions::from_table(options.as_ref())?;
+        let content = encoding.encode(&content.as_bytes());
+        match write_file_impl(Path::new(&path), &content, &write_options).await {
             Ok(_) => Ok(true),
             Err(_) => Ok(false),
         }
     })?;
     globals.set("write_file", write_file)?;
 
+    // Читает файл как есть, без проверки UTF-8 (строка Lua с произвольными байтами)
+    // local png = read_bytes("logo.png")
+    // println(#png .. " байт, сигнатура: " .. png:sub(2, 4))
+    // Возвращает: string | nil (содержимое файла или nil при ошибке)
+    let read_bytes = lua.create_async_function(|lua, path: String| async move {
+        match tokio::fs::read(path).await {
+            Ok(content) => Ok(Some(lua.create_string(content)?)),
+            Err(_) => Ok(None),
+        }
+    })?;
+    globals.set("read_bytes", read_bytes)?;
+
+    // Записывает байты строки Lua в файл без перекодирования
+    // write_bytes("copy.png", read_bytes("logo.png"))
+    // write_bytes("firmware.bin", header .. payload, {atomic = true, mode = "600"})
+    // Опции: atomic, append, mode, create_dirs - как у write_file
+    // Возвращает: boolean (true если успешно записан)
+    let write_bytes = lua.create_async_function(|_, (path, content, options): (String, mlua::String, Option<mlua::Table>)| async move {
+        let write_options = WriteFileOptions::from_table(options.as_ref())?;
+        match write_file_impl(Path::new(&path), &content.as_bytes(), &write_options).await {
+            Ok(_) => Ok(true),
+            Err(_) => Ok(false),
+        }
+    })?;
+    globals.set("write_bytes", write_bytes)?;
+
     // ================ Операции с директориями ================
 
     // Создает директорию и все родительские папки
@@ -102,27 +165,24 @@ pub fn setup_globals_io(
     })?;
     globals.set("copy_file", copy_file)?;
 
-    // Рекурсивно копирует директорию со всем содержимым
-    // copy_dir("src_folder", "dest_folder")
-    // Возвращает: boolean (true если успешно скопирована)
-    let copy_dir = lua.create_async_function(|_, (src, dest , dir_ignore): (String, String , String)| async move {
-        match copy_dir_iterative(&src, &dest , &dir_ignore).await {
-            Ok(_) => Ok(true),
-            Err(_) => Ok(false),
-        }
-    })?;
-    globals.set("copy_dir", copy_dir)?;
-
     // ================ Получение списков файлов и директорий ================
 
     // Получает список всех файлов и папок в директории
     // local items = list_dir("src")
+    // local items = list_dir(".", {gitignore = true})
+    // Опции: gitignore (boolean) - пропускать пути из .gitignore, .ignore и .solverignore
     // Возвращает: table | nil (массив строк с именами файлов и папок)
-    let list_dir = lua.create_async_function(|_, path: String| async move {
+    let list_dir = lua.create_async_function(|_, (path, options): (String, Option<mlua::Table>)| async move {
+        let Ok(filter) = IgnoreFilter::new(&path, gitignore_option(options.as_ref())?).await else {
+            return Ok(None);
+        };
         match tokio::fs::read_dir(&path).await {
             Ok(mut entries) => {
                 let mut items = Vec::new();
                 while let Some(entry) = entries.next_entry().await.map_err(|e| mlua::Error::external(e))? {
+                    if filter.is_ignored(&entry.file_name()) {
+                        continue;
+                    }
                     if let Some(name) = entry.file_name().to_str() {
                         items.push(name.to_string());
                     }
@@ -137,12 +197,19 @@ pub fn setup_globals_io(
 
     // Получает только файлы в директории (исключая папки)
     // local files = list_files("src")
+    // Опции: gitignore (boolean) - пропускать пути из .gitignore, .ignore и .solverignore
     // Возвращает: table | nil (массив строк с именами только файлов)
-    let list_files = lua.create_async_function(|_, path: String| async move {
+    let list_files = lua.create_async_function(|_, (path, options): (String, Option<mlua::Table>)| async move {
+        let Ok(filter) = IgnoreFilter::new(&path, gitignore_option(options.as_ref())?).await else {
+            return Ok(None);
+        };
         match tokio::fs::read_dir(&path).await {
             Ok(mut entries) => {
                 let mut files = Vec::new();
                 while let Some(entry) = entries.next_entry().await.map_err(|e| mlua::Error::external(e))? {
+                    if filter.is_ignored(&entry.file_name()) {
+                        continue;
+                    }
                     let metadata = entry.metadata().await.map_err(|e| mlua::Error::external(e))?;
                     if metadata.is_file() {
                         if let Some(name) = entry.file_name().to_str() {
@@ -160,12 +227,19 @@ pub fn setup_globals_io(
 
     // Получает только директории в папке (исключая файлы)
     // local dirs = list_dirs("src")
+    // Опции: gitignore (boolean) - пропускать пути из .gitignore, .ignore и .solverignore
     // Возвращает: table | nil (массив строк с именами только папок)
-    let list_dirs = lua.create_async_function(|_, path: String| async move {
+    let list_dirs = lua.create_async_function(|_, (path, options): (String, Option<mlua::Table>)| async move {
+        let Ok(filter) = IgnoreFilter::new(&path, gitignore_option(options.as_ref())?).await else {
+            return Ok(None);
+        };
         match tokio::fs::read_dir(&path).await {
             Ok(mut entries) => {
                 let mut dirs = Vec::new();
                 while let Some(entry) = entries.next_entry().await.map_err(|e| mlua::Error::external(e))? {
+                    if filter.is_ignored(&entry.file_name()) {
+                        continue;
+                    }
                     let metadata = entry.metadata().await.map_err(|e| mlua::Error::external(e))?;
                     if metadata.is_dir() {
                         if let Some(name) = entry.file_name().to_str() {
@@ -183,9 +257,12 @@ pub fn setup_globals_io(
 
     // Рекурсивно получает все файлы в директории и поддиректориях
     // local all_files = list_files_recursive("src")
+    // local sources = list_files_recursive(".", {gitignore = true})  -- без target/, node_modules/ и .git/
+    // Опции: gitignore (boolean) - не заходить в папки и пропускать файлы из .gitignore, .ignore и .solverignore
+    //        (как в git: с отрицанием "!", вложенными файлами исключений и правилами родительских папок)
     // Возвращает: table | nil (массив строк с полными путями к файлам)
-    let list_files_recursive = lua.create_async_function(|_, path: String| async move {
-        match list_files_recursive_impl(&path).await {
+    let list_files_recursive = lua.create_async_function(|_, (path, options): (String, Option<mlua::Table>)| async move {
+        match list_files_recursive_impl(&path, gitignore_option(options.as_ref())?).await {
             Ok(files) => Ok(Some(files)),
             Err(_) => Ok(None),
         }
@@ -194,12 +271,20 @@ pub fn setup_globals_io(
 
     // Получает детальную информацию о содержимом директории
     // local info = list_dir_detailed("src")
+    // Опции: gitignore (boolean) - пропускать пути из .gitignore, .ignore и .solverignore
     // Возвращает: table | nil с элементами {name, type, size, modified}
-    let list_dir_detailed = lua.create_async_function(|lua, path: String| async move {
+    //   type: "file", "directory", "symlink" (ссылка не разыменовывается) или "other"
+    let list_dir_detailed = lua.create_async_function(|lua, (path, options): (String, Option<mlua::Table>)| async move {
+        let Ok(filter) = IgnoreFilter::new(&path, gitignore_option(options.as_ref())?).await else {
+            return Ok(None);
+        };
         match tokio::fs::read_dir(&path).await {
             Ok(mut entries) => {
                 let mut items = Vec::new();
                 while let Some(entry) = entries.next_entry().await.map_err(|e| mlua::Error::external(e))? {
+                    if filter.is_ignored(&entry.file_name()) {
+                        continue;
+                    }
                     if let Ok(metadata) = entry.metadata().await {
                         let item = lua.create_table()?;
 
@@ -207,8 +292,7 @@ pub fn setup_globals_io(
                             item.set("name", name)?;
                         }
 
-                        let item_type = if metadata.is_file() { "file" } else { "directory" };
-                        item.set("type", item_type)?;
+                        item.set("type", file_type_name(&metadata.file_type()))?;
                         item.set("size", metadata.len())?;
 
                         if let Ok(modified) = metadata.modified() {
@@ -230,8 +314,12 @@ pub fn setup_globals_io(
     // Фильтрует файлы по расширению
     // local rs_files = list_files_by_extension("src", "rs")
     // local all_rs_files = list_files_by_extension(".", "rs") -- текущая папка
+    // Опции: gitignore (boolean) - пропускать пути из .gitignore, .ignore и .solverignore
     // Возвращает: table | nil (массив файлов с указанным расширением)
-    let list_files_by_extension = lua.create_async_function(|_, (path, extension): (String, String)| async move {
+    let list_files_by_extension = lua.create_async_function(|_, (path, extension, options): (String, String, Option<mlua::Table>)| async move {
+        let Ok(filter) = IgnoreFilter::new(&path, gitignore_option(options.as_ref())?).await else {
+            return Ok(None);
+        };
         match tokio::fs::read_dir(&path).await {
             Ok(mut entries) => {
                 let mut files = Vec::new();
@@ -242,6 +330,9 @@ pub fn setup_globals_io(
                 };
 
                 while let Some(entry) = entries.next_entry().await.map_err(|e| mlua::Error::external(e))? {
+                    if filter.is_ignored(&entry.file_name()) {
+                        continue;
+                    }
                     let metadata = entry.metadata().await.map_err(|e| mlua::Error::external(e))?;
                     if metadata.is_file() {
                         if let Some(name) = entry.file_name().to_str() {
@@ -312,39 +403,145 @@ pub fn setup_globals_io(
     Ok(())
 }
 
-async fn copy_dir_iterative(src: &str, dest: &str , dir_ignore: &str) -> tokio::io::Result<()> {
-    use std::collections::VecDeque;
-    use tokio::fs;
+// Опции записи файла для write_file и write_bytes
+struct WriteFileOptions {
+    atomic: bool,
+    append: bool,
+    mode: Option<u32>,
+    create_dirs: bool,
+}
 
-    let mut queue = VecDeque::new();
-    queue.push_back((src.to_string(), dest.to_string()));
+impl WriteFileOptions {
+    fn from_table(options: Option<&mlua::Table>) -> mlua::Result<Self> {
+        let Some(options) = options else {
+            return Ok(Self {
+                atomic: false,
+                append: false,
+                mode: None,
+                create_dirs: false,
+            });
+        };
+        let result = Self {
+            atomic: options.get::<Option<bool>>("atomic")?.unwrap_or(false),
+            append: options.get::<Option<bool>>("append")?.unwrap_or(false),
+            mode: parse_mode(options.get::<mlua::Value>("mode")?)?,
+            create_dirs: options.get::<Option<bool>>("create_dirs")?.unwrap_or(false),
+        };
+        if result.atomic && result.append {
+            return Err(mlua::Error::external("Options 'atomic' and 'append' cannot be used together"));
+        }
+        Ok(result)
+    }
+}
 
-    while let Some((current_src, current_dest)) = queue.pop_front() {
-        fs::create_dir_all(&current_dest).await?;
+// Права доступа: восьмеричная строка ("644", "0o755", "0755") или число
+pub(crate) fn parse_mode(value: mlua::Value) -> mlua::Result<Option<u32>> {
+    let invalid = || mlua::Error::external("Option 'mode' must be an octal string like \"644\" or a number");
+    match value {
+        mlua::Value::Nil => Ok(None),
+        mlua::Value::Integer(mode) => u32::try_from(mode).map(Some).map_err(|_| invalid()),
+        mlua::Value::String(mode) => {
+            let mode = mode.to_str()?;
+            let digits = mode.strip_prefix("0o").unwrap_or(&mode);
+            u32::from_str_radix(digits, 8).map(Some).map_err(|_| invalid())
+        }
+        _ => Err(invalid()),
+    }
+}
 
-        let mut entries = fs::read_dir(&current_src).await?;
-        while let Some(entry) = entries.next_entry().await? {
-            let entry_path = entry.path();
-            let dest_path = Path::new(&current_dest).join(entry.file_name());
-
-            if entry_path.is_dir() {
-                // Пропускаем директорию obfuscation
-                if entry.file_name() != dir_ignore {
-                    queue.push_back((
-                        entry_path.to_string_lossy().to_string(),
-                        dest_path.to_string_lossy().to_string(),
-                    ));
+async fn write_file_impl(path: &Path, content: &[u8], options: &WriteFileOptions) -> tokio::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    if options.create_dirs
+        && let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty())
+    {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    if options.atomic {
+        return write_atomic(path, content, options.mode).await;
+    }
+
+    let mut open_options = tokio::fs::OpenOptions::new();
+    open_options.create(true);
+    if options.append {
+        open_options.append(true);
+    } else {
+        open_options.write(true).truncate(true);
+    }
+    // Новый файл сразу создается с нужными правами, а не открывается сначала всем по umask
+    #[cfg(unix)]
+    if let Some(mode) = options.mode {
+        open_options.mode(mode);
+    }
+    let mut file = open_options.open(path).await?;
+    file.write_all(content).await?;
+    file.flush().await?;
+    drop(file);
+    // Права существующего файла (и биты, снятые umask при создании) меняются отдельно
+    if let Some(mode) = options.mode {
+        set_mode(path, mode).await?;
+    }
+    Ok(())
+}
+
+// Пишет во временный файл в той же папке и переименовывает его поверх path:
+// другие процессы видят либо старое, либо полностью записанное содержимое
+async fn write_atomic(path: &Path, content: &[u8], mode: Option<u32>) -> tokio::io::Result<()> {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tokio::io::AsyncWriteExt;
+
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let name = path.file_name().ok_or_else(|| std::io::Error::other("path has no file name"))?;
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        name.to_string_lossy(),
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = async {
+        let mut open_options = tokio::fs::OpenOptions::new();
+        open_options.write(true).create_new(true);
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            open_options.mode(mode);
+        }
+        let mut file = open_options.open(&temp_path).await?;
+        file.write_all(content).await?;
+        file.sync_all().await?;
+        drop(file);
+
+        match mode {
+            Some(mode) => set_mode(&temp_path, mode).await?,
+            None => {
+                if let Ok(metadata) = tokio::fs::metadata(path).await {
+                    tokio::fs::set_permissions(&temp_path, metadata.permissions()).await?;
                 }
-            } else {
-                fs::copy(&entry_path, &dest_path).await?;
             }
         }
+        tokio::fs::rename(&temp_path, path).await
+    }
+    .await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&temp_path).await;
     }
+    result
+}
 
+#[cfg(unix)]
+async fn set_mode(path: &Path, mode: u32) -> tokio::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await
+}
+
+// На Windows права Unix не поддерживаются
+#[cfg(not(unix))]
+async fn set_mode(_path: &Path, _mode: u32) -> tokio::io::Result<()> {
     Ok(())
 }
 
-async fn list_files_recursive_impl(path: &str) -> tokio::io::Result<Vec<String>> {
+async fn list_files_recursive_impl(path: &str, gitignore: bool) -> tokio::io::Result<Vec<String>> {
     use std::collections::VecDeque;
     use tokio::fs;
 
@@ -353,8 +550,12 @@ async fn list_files_recursive_impl(path: &str) -> tokio::io::Result<Vec<String>>
     queue.push_back(path.to_string());
 
     while let Some(current_path) = queue.pop_front() {
+        let filter = IgnoreFilter::new(&current_path, gitignore).await?;
         let mut entries = fs::read_dir(&current_path).await?;
         while let Some(entry) = entries.next_entry().await? {
+            if filter.is_ignored(&entry.file_name()) {
+                continue;
+            }
             let entry_path = entry.path();
             let metadata = entry.metadata().await?;
 
@@ -368,4 +569,35 @@ async fn list_files_recursive_impl(path: &str) -> tokio::io::Result<Vec<String>>
 
     files.sort();
     Ok(files)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_octal_strings() {
+        let lua = Lua::new();
+        for (text, mode) in [("644", 0o644), ("0755", 0o755), ("0o600", 0o600), ("4755", 0o4755)] {
+            let value = mlua::Value::String(lua.create_string(text).unwrap());
+            assert_eq!(parse_mode(value).unwrap(), Some(mode), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parses_numbers_and_nil() {
+        assert_eq!(parse_mode(mlua::Value::Integer(0o644)).unwrap(), Some(0o644));
+        assert_eq!(parse_mode(mlua::Value::Nil).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_invalid_modes() {
+        let lua = Lua::new();
+        for text in ["", "999", "rwx", "0x644", "0o"] {
+            let value = mlua::Value::String(lua.create_string(text).unwrap());
+            assert!(parse_mode(value).is_err(), "{}", text);
+        }
+        assert!(parse_mode(mlua::Value::Integer(-1)).is_err());
+        assert!(parse_mode(mlua::Value::Boolean(true)).is_err());
+    }
+}
@@ -0,0 +1,410 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+#[cfg(unix)]
+use std::fs::File;
+#[cfg(unix)]
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use tokio::io::unix::AsyncFd;
+
+// Общий на весь процесс пул слотов для параллельных задач (протокол jobserver GNU make)
+// Solver сам владеет одним "неявным" слотом, остальные слоты - байты в канале jobserver'а
+static JOBSERVER: OnceLock<Jobserver> = OnceLock::new();
+
+pub struct Jobserver {
+    backend: Backend,
+    implicit_free: AtomicBool,
+    // Будит задачи, ожидающие слот в канале, когда освобождается неявный слот
+    implicit_released: Notify,
+    limit: usize,
+    // MAKEFLAGS собственного jobserver'а для дочерних процессов (у унаследованного он уже в окружении)
+    makeflags: Option<String>,
+    // --jobs указан явно: jobserver передается всем дочерним процессам
+    explicit: bool,
+}
+
+enum Backend {
+    // Канал make: унаследованный из MAKEFLAGS или созданный Solver'ом для дочерних процессов
+    // Читаем через собственный неблокирующий дескриптор, чтобы ожидание можно было отменить
+    #[cfg(unix)]
+    Pipe { read: AsyncFd<File>, write: File },
+    // Локальный пул, если канал недоступен (Windows)
+    Local(Arc<Semaphore>),
+}
+
+// Занятый слот; освобождается при удалении
+pub struct JobToken {
+    kind: TokenKind,
+}
+
+enum TokenKind {
+    Implicit,
+    #[cfg(unix)]
+    Pipe(u8),
+    // Разрешение семафора возвращается при удалении токена
+    Local(#[allow(dead_code)] OwnedSemaphorePermit),
+    // Канал сломан: работаем без ограничения, чтобы не зависнуть
+    Free,
+}
+
+impl Drop for JobToken {
+    fn drop(&mut self) {
+        let Some(jobserver) = JOBSERVER.get() else {
+            return;
+        };
+        match &self.kind {
+            TokenKind::Implicit => {
+                jobserver.implicit_free.store(true, Ordering::Release);
+                jobserver.implicit_released.notify_one();
+            }
+            #[cfg(unix)]
+            TokenKind::Pipe(byte) => {
+                if let Backend::Pipe { write, .. } = &jobserver.backend {
+                    let _ = (&*write).write_all(&[*byte]);
+                }
+            }
+            TokenKind::Local(_) | TokenKind::Free => {}
+        }
+    }
+}
+
+// Настраивает jobserver при запуске: подключается к jobserver'у make из MAKEFLAGS,
+// а если его нет - создает собственный на jobs слотов
+pub fn init_jobserver(jobs: Option<usize>, verbose: bool) {
+    let mut jobserver = create_jobserver(jobs.map(|jobs| jobs.max(1)), verbose);
+    jobserver.explicit = jobs.is_some();
+    let _ = JOBSERVER.set(jobserver);
+}
+
+// Передает собственный jobserver дочернему процессу через MAKEFLAGS, чтобы make, cargo и Solver
+// брали слоты из общего пула. Окружение самого Solver'а не меняется: команды parallel и cargo
+// (job_aware) получают jobserver всегда, остальные - только при явном --jobs,
+// иначе обычный make из task_run неожиданно стал бы параллельным
+pub fn set_jobserver_env(command: &mut tokio::process::Command, job_aware: bool) {
+    let jobserver = jobserver();
+    if let Some(makeflags) = &jobserver.makeflags
+        && (job_aware || jobserver.explicit)
+    {
+        command.env("MAKEFLAGS", makeflags);
+    }
+}
+
+// Возвращает jobserver процесса (при первом обращении без init_jobserver - с числом ядер)
+pub fn jobserver() -> &'static Jobserver {
+    JOBSERVER.get_or_init(|| create_jobserver(None, false))
+}
+
+impl Jobserver {
+    // Максимальное число одновременных задач: --jobs, а для унаследованного jobserver'а - -jN из MAKEFLAGS
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    // Ожидает свободный слот
+    pub async fn acquire(&'static self) -> JobToken {
+        loop {
+            let released = self.implicit_released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            if self
+                .implicit_free
+                .compare_exchange(true, false, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                return JobToken { kind: TokenKind::Implicit };
+            }
+
+            tokio::select! {
+                token = self.acquire_shared() => return token,
+                _ = released => {}
+            }
+        }
+    }
+
+    // Слот из общего пула (канала или семафора)
+    async fn acquire_shared(&self) -> JobToken {
+        match &self.backend {
+            #[cfg(unix)]
+            Backend::Pipe { read, .. } => match read_token(read).await {
+                Ok(byte) => JobToken { kind: TokenKind::Pipe(byte) },
+                Err(_) => JobToken { kind: TokenKind::Free },
+            },
+            Backend::Local(semaphore) => match semaphore.clone().acquire_owned().await {
+                Ok(permit) => JobToken { kind: TokenKind::Local(permit) },
+                Err(_) => JobToken { kind: TokenKind::Free },
+            },
+        }
+    }
+}
+
+#[cfg(unix)]
+fn create_jobserver(jobs: Option<usize>, verbose: bool) -> Jobserver {
+    if let Some(jobserver) = inherited_jobserver(jobs, verbose) {
+        return jobserver;
+    }
+
+    let jobs = jobs.unwrap_or_else(default_jobs);
+    // Исходный дескриптор чтения остается открытым для дочерних процессов
+    let pipe = create_pipe(jobs).and_then(|(read, write, fds)| {
+        let reader = async_reader(&read, None)?;
+        std::mem::forget(read);
+        Ok((reader, write, fds))
+    });
+    match pipe {
+        Ok((read, write, fds)) => {
+            // Передается дочерним процессам через set_jobserver_env
+            let makeflags = format!("-j{} --jobserver-auth={},{}", jobs, fds.0, fds.1);
+            if verbose {
+                println!("Jobserver: {} jobs (MAKEFLAGS={})", jobs, makeflags);
+            }
+            Jobserver {
+                backend: Backend::Pipe { read, write },
+                implicit_free: AtomicBool::new(true),
+                implicit_released: Notify::new(),
+                limit: jobs,
+                makeflags: Some(makeflags),
+                explicit: false,
+            }
+        }
+        Err(e) => {
+            eprintln!("Jobserver: failed to create pipe ({}), using local job limit", e);
+            local_jobserver(jobs)
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn create_jobserver(jobs: Option<usize>, verbose: bool) -> Jobserver {
+    let jobs = jobs.unwrap_or_else(default_jobs);
+    if verbose {
+        println!("Jobserver: {} jobs", jobs);
+    }
+    local_jobserver(jobs)
+}
+
+fn default_jobs() -> usize {
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4)
+}
+
+fn local_jobserver(jobs: usize) -> Jobserver {
+    Jobserver {
+        backend: Backend::Local(Arc::new(Semaphore::new(jobs - 1))),
+        implicit_free: AtomicBool::new(true),
+        implicit_released: Notify::new(),
+        limit: jobs,
+        makeflags: None,
+        explicit: false,
+    }
+}
+
+// Подключение к jobserver'у make, указанное в MAKEFLAGS
+#[cfg(unix)]
+#[derive(Debug, PartialEq)]
+enum MakeflagsAuth<'a> {
+    // "--jobserver-auth=fifo:PATH"
+    Fifo(&'a str),
+    // "--jobserver-auth=R,W" или "--jobserver-fds=R,W"
+    Fds(i32, i32),
+}
+
+#[cfg(unix)]
+impl std::fmt::Display for MakeflagsAuth<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fifo(path) => write!(f, "fifo:{}", path),
+            Self::Fds(read, write) => write!(f, "{},{}", read, write),
+        }
+    }
+}
+
+// Разбирает MAKEFLAGS: подключение к jobserver'у (действует последнее указанное) и число задач -jN
+// Возвращает: None, если jobserver'а нет или он указан некорректно
+#[cfg(unix)]
+fn parse_makeflags(makeflags: &str) -> Option<(MakeflagsAuth<'_>, Option<usize>)> {
+    let auth = makeflags
+        .split_whitespace()
+        .filter_map(|flag| {
+            flag.strip_prefix("--jobserver-auth=")
+                .or_else(|| flag.strip_prefix("--jobserver-fds="))
+        })
+        .next_back()?;
+    let make_jobs = makeflags
+        .split_whitespace()
+        .filter_map(|flag| flag.strip_prefix("-j")?.parse::<usize>().ok())
+        .next_back();
+
+    let auth = match auth.strip_prefix("fifo:") {
+        Some(path) if !path.is_empty() => MakeflagsAuth::Fifo(path),
+        Some(_) => return None,
+        None => {
+            let (read, write) = auth.split_once(',')?;
+            MakeflagsAuth::Fds(read.parse().ok()?, write.parse().ok()?)
+        }
+    };
+    Some((auth, make_jobs))
+}
+
+// Подключается к jobserver'у из MAKEFLAGS: "--jobserver-auth=R,W" (make 4.2+),
+// "--jobserver-fds=R,W" (старые версии) или "--jobserver-auth=fifo:PATH" (make 4.4+)
+// Слоты общие с make, а --jobs (если указан) дополнительно ограничивает задачи самого Solver'а
+#[cfg(unix)]
+fn inherited_jobserver(jobs: Option<usize>, verbose: bool) -> Option<Jobserver> {
+    use std::os::fd::FromRawFd;
+
+    let makeflags = std::env::var("MAKEFLAGS").ok()?;
+    let (auth, make_jobs) = parse_makeflags(&makeflags)?;
+    let limit = jobs.or(make_jobs).unwrap_or_else(default_jobs).max(1);
+    let backend = match auth {
+        MakeflagsAuth::Fifo(path) => {
+            let fifo = std::fs::OpenOptions::new().read(true).write(true).open(path);
+            match fifo.and_then(|fifo| Ok((async_reader(&fifo, Some(Path::new(path)))?, fifo))) {
+                Ok((read, write)) => Backend::Pipe { read, write },
+                Err(e) => {
+                    eprintln!("Jobserver: cannot open {} from MAKEFLAGS ({}), ignoring it", path, e);
+                    return None;
+                }
+            }
+        }
+        MakeflagsAuth::Fds(read, write) => {
+            // make закрывает дескрипторы для команд, которые не считает рекурсивными (без "+" или $(MAKE))
+            let valid = |fd: i32| unsafe { libc::fcntl(fd, libc::F_GETFD) } != -1;
+            if !valid(read) || !valid(write) {
+                eprintln!(
+                    "Jobserver: file descriptors {},{} from MAKEFLAGS are not available \
+                     (prefix the make rule with '+'), ignoring them",
+                    read, write
+                );
+                return None;
+            }
+            // Исходный дескриптор чтения тоже не закрываем
+            let (read, write) = unsafe { (File::from_raw_fd(read), File::from_raw_fd(write)) };
+            let backend = async_reader(&read, None).map(|read| Backend::Pipe { read, write });
+            std::mem::forget(read);
+            match backend {
+                Ok(backend) => backend,
+                Err(e) => {
+                    eprintln!("Jobserver: cannot use file descriptors from MAKEFLAGS ({}), ignoring them", e);
+                    return None;
+                }
+            }
+        }
+    };
+
+    if verbose {
+        println!("Jobserver: using jobserver from make ({}), up to {} jobs", auth, limit);
+    }
+    Some(Jobserver {
+        backend,
+        implicit_free: AtomicBool::new(true),
+        implicit_released: Notify::new(),
+        limit,
+        makeflags: None,
+        explicit: false,
+    })
+}
+
+// Канал без CLOEXEC, чтобы дескрипторы унаследовали дочерние процессы; в нем jobs - 1 слотов
+#[cfg(unix)]
+fn create_pipe(jobs: usize) -> std::io::Result<(File, File, (i32, i32))> {
+    use std::os::fd::FromRawFd;
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let read = unsafe { File::from_raw_fd(fds[0]) };
+    let mut write = unsafe { File::from_raw_fd(fds[1]) };
+    write.write_all(&vec![b'+'; jobs - 1])?;
+    Ok((read, write, (fds[0], fds[1])))
+}
+
+// Открывает канал для чтения заново, получая отдельное описание файла с O_NONBLOCK:
+// режим общего описания не меняется, и дочерние процессы по-прежнему читают его блокирующе
+#[cfg(unix)]
+fn async_reader(file: &File, path: Option<&Path>) -> std::io::Result<AsyncFd<File>> {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let fd_path = PathBuf::from(format!("/proc/self/fd/{}", file.as_raw_fd()));
+    let reopened = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path.unwrap_or(&fd_path));
+    let reader = match reopened {
+        Ok(reader) => reader,
+        // Без /proc (macOS, BSD) неблокирующим становится общее описание канала
+        Err(_) => {
+            let reader = file.try_clone()?;
+            let flags = unsafe { libc::fcntl(reader.as_raw_fd(), libc::F_GETFL) };
+            if flags == -1 || unsafe { libc::fcntl(reader.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) } == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            reader
+        }
+    };
+    AsyncFd::new(reader)
+}
+
+// Читает один байт-слот из канала; ожидание можно отменить без потери слота
+#[cfg(unix)]
+async fn read_token(read: &AsyncFd<File>) -> std::io::Result<u8> {
+    loop {
+        let mut guard = read.readable().await?;
+        let mut byte = [0u8];
+        match guard.try_io(|fd| fd.get_ref().read(&mut byte)) {
+            Ok(Ok(1)) => return Ok(byte[0]),
+            Ok(Ok(_)) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Ok(Err(e)) => return Err(e),
+            // Слот успел забрать другой процесс
+            Err(_would_block) => continue,
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_jobserver_auth_fds() {
+        assert_eq!(parse_makeflags("-j8 --jobserver-auth=3,4"), Some((MakeflagsAuth::Fds(3, 4), Some(8))));
+        assert_eq!(parse_makeflags(" --jobserver-fds=5,6 -j"), Some((MakeflagsAuth::Fds(5, 6), None)));
+    }
+
+    #[test]
+    fn parses_jobserver_fifo() {
+        assert_eq!(
+            parse_makeflags("-j4 --jobserver-auth=fifo:/tmp/GMfifo123"),
+            Some((MakeflagsAuth::Fifo("/tmp/GMfifo123"), Some(4)))
+        );
+        assert_eq!(parse_makeflags("--jobserver-auth=fifo:"), None);
+    }
+
+    #[test]
+    fn last_flags_win() {
+        assert_eq!(
+            parse_makeflags("-j2 --jobserver-auth=3,4 -j6 --jobserver-auth=7,8"),
+            Some((MakeflagsAuth::Fds(7, 8), Some(6)))
+        );
+    }
+
+    #[test]
+    fn ignores_makeflags_without_jobserver() {
+        assert_eq!(parse_makeflags(""), None);
+        assert_eq!(parse_makeflags("-j8 -k"), None);
+        assert_eq!(parse_makeflags("--jobserver-auth=3"), None);
+        assert_eq!(parse_makeflags("--jobserver-auth=a,b"), None);
+    }
+
+    #[test]
+    fn displays_auth_like_makeflags() {
+        assert_eq!(MakeflagsAuth::Fds(3, 4).to_string(), "3,4");
+        assert_eq!(MakeflagsAuth::Fifo("/tmp/f").to_string(), "fifo:/tmp/f");
+    }
+}
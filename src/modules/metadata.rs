@@ -0,0 +1,392 @@
+use crate::modules::copy::{Overwrite, WriteOptions, copy_file_entry, copy_symlink, remove_path};
+use crate::modules::io::parse_mode;
+use mlua::{Lua, Value};
+use std::fs::{self, File, FileType, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub fn setup_globals_metadata(
+    lua: Lua,
+    script_path: String,
+    target: &Option<String>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let globals = lua.globals();
+
+    // ================ Информация о файлах ================
+
+    // Получает информацию о файле, папке или ссылке
+    // local info = stat("build/app")
+    // if info and info.modified > stat("src/main.rs").modified then ... end
+    // local link = stat("current", {follow = false})             -- сама ссылка, а не файл, на который она указывает
+    // Поля: type ("file", "directory", "symlink", "other"), size, modified, accessed, created
+    //       (секунды с 1970 года, created есть не на всех системах), mode (права, число), readonly,
+    //       uid, gid (только Unix), is_symlink, target (куда указывает ссылка)
+    // На Windows mode вычисляется по атрибуту "только чтение": 444 или 666 (755 для папок)
+    // Опции: follow (boolean, по умолчанию true) - для ссылки возвращать данные файла, на который она указывает
+    //        (для битой ссылки - данные самой ссылки)
+    // Возвращает: table | nil, string (информация или nil и сообщение об ошибке)
+    let stat = lua.create_async_function(|lua, (path, options): (String, Option<mlua::Table>)| async move {
+        let follow = match &options {
+            Some(options) => options.get::<Option<bool>>("follow")?.unwrap_or(true),
+            None => true,
+        };
+        let result = tokio::task::spawn_blocking(move || -> io::Result<_> {
+            let link_metadata = fs::symlink_metadata(&path)?;
+            let is_symlink = link_metadata.file_type().is_symlink();
+            let target = if is_symlink { Some(fs::read_link(&path)?) } else { None };
+            let metadata = match follow && is_symlink {
+                true => fs::metadata(&path).unwrap_or(link_metadata),
+                false => link_metadata,
+            };
+            Ok((metadata, is_symlink, target))
+        })
+        .await
+        .map_err(mlua::Error::external)?;
+
+        let (metadata, is_symlink, link_target) = match result {
+            Ok(result) => result,
+            Err(e) => return Ok((None, Some(e.to_string()))),
+        };
+        let info = lua.create_table()?;
+        info.set("type", file_type_name(&metadata.file_type()))?;
+        info.set("size", metadata.len())?;
+        info.set("modified", metadata.modified().ok().and_then(unix_seconds))?;
+        info.set("accessed", metadata.accessed().ok().and_then(unix_seconds))?;
+        info.set("created", metadata.created().ok().and_then(unix_seconds))?;
+        info.set("mode", file_mode(&metadata))?;
+        info.set("readonly", metadata.permissions().readonly())?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            info.set("uid", metadata.uid())?;
+            info.set("gid", metadata.gid())?;
+        }
+        info.set("is_symlink", is_symlink)?;
+        info.set("target", link_target.map(|target| target.to_string_lossy().into_owned()))?;
+        Ok((Some(info), None))
+    })?;
+    globals.set("stat", stat)?;
+
+    // ================ Права и время изменения ================
+
+    // Меняет права доступа
+    // chmod("scripts/deploy.sh", "755")
+    // chmod("config/secret.txt", tonumber("600", 8))
+    // На Windows учитывается только право записи: без него файл становится "только для чтения"
+    // Возвращает: boolean, string? (true или false и сообщение об ошибке)
+    let chmod = lua.create_async_function(|_, (path, mode): (String, Value)| async move {
+        let mode = parse_mode(mode)?.ok_or_else(|| mlua::Error::external("chmod: mode is required"))?;
+        let result = tokio::task::spawn_blocking(move || set_permissions(Path::new(&path), mode))
+            .await
+            .map_err(mlua::Error::external)?;
+        Ok(status(result))
+    })?;
+    globals.set("chmod", chmod)?;
+
+    // Устанавливает время изменения файла или папки
+    // set_mtime("dist/app.zip", stat("src/main.rs").modified)
+    // set_mtime("build/.stamp", os.time() - 3600)
+    // Время - секунды с 1970 года (можно дробные)
+    // Возвращает: boolean, string? (true или false и сообщение об ошибке)
+    let set_mtime = lua.create_async_function(|_, (path, time): (String, f64)| async move {
+        let modified = system_time(time)?;
+        let result = tokio::task::spawn_blocking(move || set_times(Path::new(&path), None, modified))
+            .await
+            .map_err(mlua::Error::external)?;
+        Ok(status(result))
+    })?;
+    globals.set("set_mtime", set_mtime)?;
+
+    // Создает пустой файл, если его нет, и обновляет время доступа и изменения
+    // touch("build/.stamp")
+    // touch("build/.stamp", {mtime = stat("Cargo.lock").modified})
+    // Опции: mtime (number) - время вместо текущего, в секундах с 1970 года
+    //        create_dirs (boolean) - создать недостающие родительские папки
+    // Возвращает: boolean, string? (true или false и сообщение об ошибке)
+    let touch = lua.create_async_function(|_, (path, options): (String, Option<mlua::Table>)| async move {
+        let (mtime, create_dirs) = match &options {
+            Some(options) => (
+                options.get::<Option<f64>>("mtime")?,
+                options.get::<Option<bool>>("create_dirs")?.unwrap_or(false),
+            ),
+            None => (None, false),
+        };
+        let time = match mtime {
+            Some(mtime) => system_time(mtime)?,
+            None => SystemTime::now(),
+        };
+        let result = tokio::task::spawn_blocking(move || -> io::Result<()> {
+            let path = Path::new(&path);
+            if create_dirs && let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+            if fs::symlink_metadata(path).is_err() {
+                File::options().create(true).append(true).open(path)?;
+            }
+            set_times(path, Some(time), time)
+        })
+        .await
+        .map_err(mlua::Error::external)?;
+        Ok(status(result))
+    })?;
+    globals.set("touch", touch)?;
+
+    // ================ Ссылки ================
+
+    // Создает символическую ссылку link, указывающую на target (как ln -s target link)
+    // symlink("app-1.2.0", "releases/current")                -- target относительно папки ссылки
+    // На Windows без прав на создание ссылок (режим разработчика или администратор) для папки
+    // создается junction, для файла - жесткая ссылка
+    // Возвращает: boolean, string? (true или false и сообщение об ошибке)
+    let symlink = lua.create_async_function(|_, (target, link): (String, String)| async move {
+        let result = tokio::task::spawn_blocking(move || create_symlink(Path::new(&target), Path::new(&link)))
+            .await
+            .map_err(mlua::Error::external)?;
+        Ok(status(result))
+    })?;
+    globals.set("symlink", symlink)?;
+
+    // Читает, куда указывает символическая ссылка
+    // local target = read_link("releases/current")             -- "app-1.2.0"
+    // Возвращает: string | nil (путь как он записан в ссылке или nil, если это не ссылка)
+    let read_link = lua.create_async_function(|_, path: String| async move {
+        match tokio::fs::read_link(path).await {
+            Ok(target) => Ok(Some(target.to_string_lossy().into_owned())),
+            Err(_) => Ok(None),
+        }
+    })?;
+    globals.set("read_link", read_link)?;
+
+    // Создает жесткую ссылку dest на файл src
+    // hard_link("cache/libfoo.a", "build/libfoo.a")
+    // Оба пути должны быть на одном диске
+    // Возвращает: boolean, string? (true или false и сообщение об ошибке)
+    let hard_link = lua.create_async_function(|_, (src, dest): (String, String)| async move {
+        Ok(status(tokio::fs::hard_link(src, dest).await))
+    })?;
+    globals.set("hard_link", hard_link)?;
+
+    // ================ Переименование и перемещение ================
+
+    // Переименовывает файл или папку; существующий файл dest заменяется
+    // rename("build/app.tmp", "build/app")
+    // Возвращает: boolean, string? (true или false и сообщение об ошибке)
+    let rename = lua.create_async_function(|_, (src, dest): (String, String)| async move {
+        Ok(status(tokio::fs::rename(src, dest).await))
+    })?;
+    globals.set("rename", rename)?;
+
+    // Перемещает файл или папку (как mv): если dest - существующая папка, src переносится внутрь нее
+    // move("build/app", "dist")                               -- dist/app
+    // move("build/app.zip", "dist/app-1.0.zip")
+    // Недостающие родительские папки создаются; между дисками данные копируются
+    // (со временем изменения и правами), а затем src удаляется
+    // Возвращает: boolean, string? (true или false и сообщение об ошибке)
+    let move_path = lua.create_async_function(|_, (src, dest): (String, String)| async move {
+        let result = tokio::task::spawn_blocking(move || move_path(Path::new(&src), Path::new(&dest)))
+            .await
+            .map_err(mlua::Error::external)?;
+        Ok(status(result))
+    })?;
+    globals.set("move", move_path)?;
+
+    Ok(())
+}
+
+// ================ Вспомогательные функции ================
+
+// Тип элемента для stat и list_dir_detailed
+pub(crate) fn file_type_name(file_type: &FileType) -> &'static str {
+    if file_type.is_symlink() {
+        "symlink"
+    } else if file_type.is_dir() {
+        "directory"
+    } else if file_type.is_file() {
+        "file"
+    } else {
+        "other"
+    }
+}
+
+fn status(result: io::Result<()>) -> (bool, Option<String>) {
+    match result {
+        Ok(()) => (true, None),
+        Err(e) => (false, Some(e.to_string())),
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH).ok().map(|duration| duration.as_secs())
+}
+
+fn system_time(seconds: f64) -> mlua::Result<SystemTime> {
+    Duration::try_from_secs_f64(seconds)
+        .map(|duration| UNIX_EPOCH + duration)
+        .map_err(|_| mlua::Error::external(format!("Invalid time: {}", seconds)))
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(metadata: &Metadata) -> u32 {
+    match (metadata.is_dir(), metadata.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o666,
+    }
+}
+
+#[cfg(unix)]
+fn set_permissions(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+// На Windows из прав Unix есть только запись
+#[cfg(not(unix))]
+fn set_permissions(path: &Path, mode: u32) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+// Меняет время изменения (и доступа, если задано) по пути через utimensat: в отличие от
+// дескриптора, открытого на чтение, работает и для папок, и для файлов без права чтения
+#[cfg(unix)]
+fn set_times(path: &Path, accessed: Option<SystemTime>, modified: SystemTime) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut omit: libc::timespec = unsafe { std::mem::zeroed() };
+    omit.tv_nsec = libc::UTIME_OMIT;
+    let times = [accessed.map_or(omit, timespec), timespec(modified)];
+    if unsafe { libc::utimensat(libc::AT_FDCWD, path.as_ptr(), times.as_ptr(), 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn timespec(time: SystemTime) -> libc::timespec {
+    let (seconds, nanos) = match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => (duration.as_secs() as i64, duration.subsec_nanos()),
+        // До 1970 года: наносекунды в timespec всегда неотрицательные
+        Err(e) => {
+            let duration = e.duration();
+            match duration.subsec_nanos() {
+                0 => (-(duration.as_secs() as i64), 0),
+                nanos => (-(duration.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    };
+    let mut result: libc::timespec = unsafe { std::mem::zeroed() };
+    result.tv_sec = seconds as libc::time_t;
+    result.tv_nsec = nanos as _;
+    result
+}
+
+// На Windows папку можно открыть только с FILE_FLAG_BACKUP_SEMANTICS, а для смены времени
+// достаточно права FILE_WRITE_ATTRIBUTES: время меняется и у файлов "только для чтения"
+#[cfg(windows)]
+fn set_times(path: &Path, accessed: Option<SystemTime>, modified: SystemTime) -> io::Result<()> {
+    use std::os::windows::fs::OpenOptionsExt;
+    let mut times = fs::FileTimes::new().set_modified(modified);
+    if let Some(accessed) = accessed {
+        times = times.set_accessed(accessed);
+    }
+    const FILE_WRITE_ATTRIBUTES: u32 = 0x0100;
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    File::options()
+        .access_mode(FILE_WRITE_ATTRIBUTES)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)?
+        .set_times(times)
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+// Ссылки на Windows бывают на файл и на папку: тип определяется по target относительно папки ссылки.
+// Без права SeCreateSymbolicLinkPrivilege используются junction (папки) и жесткие ссылки (файлы)
+#[cfg(windows)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    let resolved = link.parent().unwrap_or(Path::new("")).join(target);
+    let is_dir = resolved.is_dir();
+    let result = if is_dir {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    };
+    // ERROR_PRIVILEGE_NOT_HELD
+    match result {
+        Err(e) if e.raw_os_error() == Some(1314) && is_dir => {
+            let status = std::process::Command::new("cmd")
+                .arg("/C")
+                .arg("mklink")
+                .arg("/J")
+                .arg(link)
+                .arg(fs::canonicalize(&resolved)?)
+                .stdout(std::process::Stdio::null())
+                .status()?;
+            if status.success() {
+                Ok(())
+            } else {
+                Err(io::Error::other(format!("mklink /J failed for '{}'", link.display())))
+            }
+        }
+        Err(e) if e.raw_os_error() == Some(1314) && resolved.is_file() => fs::hard_link(&resolved, link),
+        result => result,
+    }
+}
+
+fn move_path(src: &Path, dest: &Path) -> io::Result<()> {
+    let dest = match (fs::metadata(dest), src.file_name()) {
+        (Ok(metadata), Some(name)) if metadata.is_dir() => dest.join(name),
+        _ => dest.to_path_buf(),
+    };
+    let source_metadata = fs::symlink_metadata(src)?;
+    if let Some(parent) = dest.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::rename(src, &dest) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_tree(src, &dest, &source_metadata)?;
+            remove_path(src)
+        }
+        result => result,
+    }
+}
+
+// Копия для перемещения между дисками: ссылки остаются ссылками, время и права сохраняются
+fn copy_tree(src: &Path, dest: &Path, metadata: &Metadata) -> io::Result<()> {
+    let options = WriteOptions {
+        overwrite: Overwrite::Always,
+        preserve_permissions: true,
+        preserve_mtime: true,
+    };
+    if metadata.file_type().is_symlink() {
+        copy_symlink(src, dest, options)?;
+    } else if metadata.is_dir() {
+        fs::create_dir_all(dest)?;
+        let mut children: Vec<PathBuf> = fs::read_dir(src)?.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<_>>()?;
+        children.sort();
+        for child in children {
+            let child_metadata = fs::symlink_metadata(&child)?;
+            let name = child.file_name().unwrap_or_default();
+            copy_tree(&child, &dest.join(name), &child_metadata)?;
+        }
+        // Время папки - после копирования содержимого, которое его меняет
+        set_times(dest, metadata.accessed().ok(), metadata.modified()?)?;
+        fs::set_permissions(dest, metadata.permissions())?;
+    } else {
+        copy_file_entry(src, dest, options)?;
+    }
+    Ok(())
+}
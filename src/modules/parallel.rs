@@ -0,0 +1,267 @@
+use crate::modules::encoding::{LineReader, TextEncoding};
+use crate::modules::interrupt::register_child;
+use crate::modules::jobserver::{jobserver, set_jobserver_env};
+use crate::modules::process::create_command;
+use crate::modules::usage::{record_usage, wait_with_usage, ProcessUsage};
+use colored::Colorize;
+use futures::stream::{self, StreamExt};
+use mlua::{Lua, Value};
+use std::time::Instant;
+
+// Задача для параллельного выполнения: shell-команда или асинхронная Lua функция
+enum ParallelTask {
+    Command(String),
+    Function(mlua::Function),
+}
+
+// Результат выполнения одной задачи
+struct TaskOutcome {
+    success: bool,
+    exit_code: Option<i32>,
+    output: Vec<String>,
+    value: Value,
+    error: Option<String>,
+    duration: f64,
+    // Ресурсы, потраченные командой (у Lua функций нет)
+    usage: Option<ProcessUsage>,
+}
+
+pub fn setup_globals_parallel(
+    lua: Lua,
+    script_path: String,
+    target: &Option<String>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let globals = lua.globals();
+
+    // ================ Параллельное выполнение ================
+
+    // Выполняет команды и Lua функции одновременно с ограничением числа задач
+    // local results = parallel({
+    //     "cargo clippy -p core",
+    //     {cmd = "cargo test -p core", label = "test"},
+    //     {fn = function() return task_run("npm run lint", print) end, label = "lint"},
+    //     function() return copy_dir("assets", "dist/assets", "") end,
+    // }, {max = 2, fail_fast = true})
+    // Каждая строка вывода задачи cmd выводится с префиксом метки: [test] running 12 tests
+    // Вывод задач fn (print, callback'и task_run) не перехватывается и выводится без префикса
+    // Опции: max (number, по умолчанию значение --jobs), fail_fast (boolean, по умолчанию false),
+    //        encoding (string) - кодировка вывода команд, как у task_run
+    // Функция считается неуспешной, если выбросила ошибку или вернула false
+    // Команды занимают слоты общего jobserver'а, поэтому вместе с cc и вложенными make
+    // одновременно выполняется не больше --jobs процессов
+    // Возвращает: таблица результатов в порядке задач
+    //   {label, success, skipped, exit_code, output, value, error, duration, usage}
+    //   usage - ресурсы команды, как у task_run (у Lua функций nil)
+    let parallel = lua.create_async_function(|lua, (tasks, options): (Vec<Value>, Option<mlua::Table>)| async move {
+        let mut max = jobserver().limit();
+        let mut fail_fast = false;
+        let mut encoding = TextEncoding::default();
+        if let Some(options) = &options {
+            max = options.get::<Option<usize>>("max")?.unwrap_or(max).max(1);
+            fail_fast = options.get::<Option<bool>>("fail_fast")?.unwrap_or(false);
+            encoding = TextEncoding::from_label(options.get::<Option<String>>("encoding")?.as_deref())?;
+        }
+
+        let mut labels = Vec::new();
+        let mut commands = Vec::new();
+        let mut parsed = Vec::new();
+        for (index, task) in tasks.into_iter().enumerate() {
+            let (label, task) = parse_task(task, index)?;
+            labels.push(label);
+            commands.push(match &task {
+                ParallelTask::Command(command) => Some(command.clone()),
+                ParallelTask::Function(_) => None,
+            });
+            parsed.push(task);
+        }
+
+        let mut outcomes: Vec<Option<TaskOutcome>> = (0..parsed.len()).map(|_| None).collect();
+
+        {
+            let mut running = stream::iter(parsed.into_iter().enumerate().map(|(index, task)| {
+                let label = labels[index].clone();
+                async move { (index, run_task(task, &label, encoding).await) }
+            }))
+            .buffer_unordered(max);
+
+            while let Some((index, outcome)) = running.next().await {
+                if let (Some(command), Some(usage)) = (&commands[index], outcome.usage) {
+                    record_usage(&lua, command, usage);
+                }
+                let failed = !outcome.success;
+                outcomes[index] = Some(outcome);
+                // При fail_fast оставшиеся задачи отменяются: незапущенные не стартуют,
+                // а запущенные команды завершаются вместе с их futures
+                if failed && fail_fast {
+                    break;
+                }
+            }
+        }
+
+        let results = lua.create_table()?;
+        for (index, outcome) in outcomes.into_iter().enumerate() {
+            let result = lua.create_table()?;
+            result.set("label", labels[index].clone())?;
+            match outcome {
+                Some(outcome) => {
+                    result.set("success", outcome.success)?;
+                    result.set("skipped", false)?;
+                    result.set("exit_code", outcome.exit_code)?;
+                    result.set("output", outcome.output.join("\n"))?;
+                    result.set("value", outcome.value)?;
+                    result.set("error", outcome.error)?;
+                    result.set("duration", outcome.duration)?;
+                    result.set("usage", outcome.usage.map(|usage| usage.to_table(&lua)).transpose()?)?;
+                }
+                None => {
+                    result.set("success", false)?;
+                    result.set("skipped", true)?;
+                }
+            }
+            results.push(result)?;
+        }
+
+        Ok(results)
+    })?;
+    globals.set("parallel", parallel)?;
+
+    Ok(())
+}
+
+// ================ Вспомогательные функции ================
+
+fn parse_task(task: Value, index: usize) -> mlua::Result<(String, ParallelTask)> {
+    match task {
+        Value::String(command) => {
+            let command = command.to_str()?.to_string();
+            Ok((command.clone(), ParallelTask::Command(command)))
+        }
+        Value::Function(function) => Ok((format!("task {}", index + 1), ParallelTask::Function(function))),
+        Value::Table(table) => {
+            let label = table.get::<Option<String>>("label")?;
+            if let Some(command) = table.get::<Option<String>>("cmd")? {
+                Ok((label.unwrap_or_else(|| command.clone()), ParallelTask::Command(command)))
+            } else if let Some(function) = table.get::<Option<mlua::Function>>("fn")? {
+                Ok((label.unwrap_or_else(|| format!("task {}", index + 1)), ParallelTask::Function(function)))
+            } else {
+                Err(mlua::Error::external(format!("parallel: task {} has neither 'cmd' nor 'fn'", index + 1)))
+            }
+        }
+        _ => Err(mlua::Error::external(format!(
+            "parallel: task {} must be a command string, a function or a table",
+            index + 1
+        ))),
+    }
+}
+
+async fn run_task(task: ParallelTask, label: &str, encoding: TextEncoding) -> TaskOutcome {
+    let start_time = Instant::now();
+    let mut outcome = match task {
+        ParallelTask::Command(command) => {
+            // Lua функции слот не занимают: внутри они сами могут запускать parallel или cc
+            let _token = jobserver().acquire().await;
+            run_labeled_command(&command, label, encoding).await
+        }
+        ParallelTask::Function(function) => match function.call_async::<Value>(()).await {
+            Ok(value) => TaskOutcome {
+                success: !matches!(value, Value::Boolean(false)),
+                exit_code: None,
+                output: Vec::new(),
+                value,
+                error: None,
+                duration: 0.0,
+                usage: None,
+            },
+            Err(e) => {
+                eprintln!("{} {}", format!("[{}]", label).red(), e);
+                TaskOutcome {
+                    success: false,
+                    exit_code: None,
+                    output: Vec::new(),
+                    value: Value::Nil,
+                    error: Some(e.to_string()),
+                    duration: 0.0,
+                    usage: None,
+                }
+            }
+        },
+    };
+    outcome.duration = start_time.elapsed().as_secs_f64();
+    outcome
+}
+
+// Выполняет команду, выводя каждую строку с префиксом метки
+async fn run_labeled_command(command: &str, label: &str, encoding: TextEncoding) -> TaskOutcome {
+    let prefix = format!("[{}]", label);
+    let mut output = Vec::new();
+
+    let mut command = create_command(command);
+    set_jobserver_env(&mut command, true);
+    let start_time = Instant::now();
+    let mut process = match command.kill_on_drop(true).spawn() {
+        Ok(process) => {
+            register_child(&process, false);
+            process
+        }
+        Err(e) => {
+            eprintln!("{} Failed to spawn process: {}", prefix.red(), e);
+            return TaskOutcome {
+                success: false,
+                exit_code: None,
+                output,
+                value: Value::Nil,
+                error: Some(format!("Failed to spawn process: {}", e)),
+                duration: 0.0,
+                usage: None,
+            };
+        }
+    };
+
+    let mut stdout_lines = LineReader::new(process.stdout.take().unwrap(), encoding);
+    let mut stderr_lines = LineReader::new(process.stderr.take().unwrap(), encoding);
+    let mut stdout_finished = false;
+    let mut stderr_finished = false;
+
+    while !stdout_finished || !stderr_finished {
+        tokio::select! {
+            line = stdout_lines.next_line(), if !stdout_finished => match line {
+                Ok(Some(line)) => {
+                    let line = String::from_utf8_lossy(&line).into_owned();
+                    println!("{} {}", prefix.cyan(), line);
+                    output.push(line);
+                }
+                _ => stdout_finished = true,
+            },
+            line = stderr_lines.next_line(), if !stderr_finished => match line {
+                Ok(Some(line)) => {
+                    let line = String::from_utf8_lossy(&line).into_owned();
+                    eprintln!("{} {}", prefix.yellow(), line);
+                    output.push(line);
+                }
+                _ => stderr_finished = true,
+            },
+        }
+    }
+
+    match wait_with_usage(&mut process, start_time).await {
+        Ok((status, usage)) => TaskOutcome {
+            success: status.success(),
+            exit_code: Some(status.code().unwrap_or(-1)),
+            output,
+            value: Value::Nil,
+            error: None,
+            duration: 0.0,
+            usage: Some(usage),
+        },
+        Err(e) => TaskOutcome {
+            success: false,
+            exit_code: None,
+            output,
+            value: Value::Nil,
+            error: Some(format!("Process wait error: {}", e)),
+            duration: 0.0,
+            usage: None,
+        },
+    }
+}
@@ -0,0 +1,155 @@
+use crate::modules::encoding::{LineReader, TextEncoding};
+use crate::modules::interrupt::register_child;
+use crate::modules::process::create_command_from_args;
+use crate::modules::usage::{record_usage, wait_with_usage};
+use mlua::Lua;
+use std::process::Stdio;
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+pub fn setup_globals_pipeline(
+    lua: Lua,
+    script_path: String,
+    target: &Option<String>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let globals = lua.globals();
+
+    // ================ Конвейеры процессов ================
+
+    // Запускает цепочку процессов, соединяя stdout каждого со stdin следующего (без shell)
+    // local result = pipeline({ {"git", "log", "--oneline"}, {"grep", "fix"} })
+    // local result = pipeline({ {"sort"}, {"uniq", "-c"} }, {
+    //     input = "b\na\nb\n",
+    //     callback = function(line) print(line) end,
+    // })
+    // Опции: cwd (string), env (table), input (string для stdin первого процесса),
+    //        callback (function(line) для вывода последнего процесса),
+    //        encoding (string) - кодировка вывода последнего процесса и stderr, как у task_run
+    // Возвращает: таблица {success, output, stages = {{command, exit_code, success, stderr, usage}, ...}}
+    // output заполняется только если callback не указан; usage - ресурсы процесса, как у task_run
+    let pipeline = lua.create_async_function(|lua, (stages, options): (Vec<mlua::Table>, Option<mlua::Table>)| async move {
+        if stages.is_empty() {
+            return Err(mlua::Error::external("pipeline: no stages given"));
+        }
+
+        let mut cwd = None;
+        let mut env = Vec::new();
+        let mut input = None;
+        let mut callback = None;
+        let mut encoding = TextEncoding::default();
+        if let Some(options) = &options {
+            encoding = TextEncoding::from_label(options.get::<Option<String>>("encoding")?.as_deref())?;
+            cwd = options.get::<Option<String>>("cwd")?;
+            if let Some(env_table) = options.get::<Option<mlua::Table>>("env")? {
+                for pair in env_table.pairs::<String, String>() {
+                    env.push(pair?);
+                }
+            }
+            input = options.get::<Option<mlua::String>>("input")?.map(|s| s.as_bytes().to_vec());
+            callback = options.get::<Option<mlua::Function>>("callback")?;
+        }
+
+        let mut commands = Vec::new();
+        let mut children = Vec::new();
+        let mut start_times = Vec::new();
+        let mut stderr_tasks = Vec::new();
+        let mut previous_stdout = None;
+
+        for (index, stage) in stages.iter().enumerate() {
+            let args: Vec<String> = stage.clone().sequence_values().collect::<mlua::Result<_>>()?;
+            commands.push(args.join(" "));
+
+            let mut cmd = create_command_from_args(stage.clone())?;
+            if let Some(cwd) = &cwd {
+                cmd.current_dir(cwd);
+            }
+            cmd.envs(env.iter().cloned());
+
+            let stdin = match previous_stdout.take() {
+                Some(stdout) => stdout,
+                None if input.is_some() => Stdio::piped(),
+                None => Stdio::null(),
+            };
+            cmd.stdin(stdin)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true);
+
+            start_times.push(Instant::now());
+            let mut child = cmd.spawn().map_err(|e| {
+                mlua::Error::external(format!("Failed to spawn stage {} ({}): {}", index + 1, commands[index], e))
+            })?;
+            register_child(&child, false);
+
+            // stderr читаем в фоне, чтобы переполненный канал не остановил процесс
+            let mut stderr = child.stderr.take().unwrap();
+            stderr_tasks.push(tokio::spawn(async move {
+                let mut buffer = Vec::new();
+                let _ = stderr.read_to_end(&mut buffer).await;
+                encoding.decode(&buffer)
+            }));
+
+            if index + 1 < stages.len() {
+                let stdout: Stdio = child.stdout.take().unwrap().try_into().map_err(|e| {
+                    mlua::Error::external(format!("Failed to connect stage {}: {}", index + 1, e))
+                })?;
+                previous_stdout = Some(stdout);
+            }
+
+            children.push(child);
+        }
+
+        if let Some(input) = input {
+            let mut stdin = children[0].stdin.take().unwrap();
+            tokio::spawn(async move {
+                let _ = stdin.write_all(&input).await;
+            });
+        }
+
+        // Читаем вывод последнего процесса построчно
+        let mut output = Vec::new();
+        let last_stdout = children.last_mut().unwrap().stdout.take().unwrap();
+        let mut reader = LineReader::new(last_stdout, encoding);
+        while let Some(line) = reader.next_line().await
+            .map_err(|e| mlua::Error::external(format!("Pipeline read error: {}", e)))?
+        {
+            match &callback {
+                Some(callback) => {
+                    callback.call_async::<mlua::Value>(lua.create_string(&line)?).await
+                        .map_err(|e| mlua::Error::external(format!("Callback error: {}", e)))?;
+                }
+                None => output.push(line),
+            }
+        }
+
+        let result = lua.create_table()?;
+        let stage_results = lua.create_table()?;
+        let mut success = true;
+
+        for (index, (mut child, stderr_task)) in children.into_iter().zip(stderr_tasks).enumerate() {
+            let (status, usage) = wait_with_usage(&mut child, start_times[index]).await
+                .map_err(|e| mlua::Error::external(format!("Process wait error: {}", e)))?;
+            record_usage(&lua, &commands[index], usage);
+            let stderr = stderr_task.await.unwrap_or_default();
+
+            success &= status.success();
+
+            let stage_result = lua.create_table()?;
+            stage_result.set("command", commands[index].clone())?;
+            stage_result.set("exit_code", status.code().unwrap_or(-1))?;
+            stage_result.set("success", status.success())?;
+            stage_result.set("stderr", lua.create_string(stderr)?)?;
+            stage_result.set("usage", usage.to_table(&lua)?)?;
+            stage_results.push(stage_result)?;
+        }
+
+        result.set("success", success)?;
+        result.set("output", lua.create_string(output.join(&b'\n'))?)?;
+        result.set("stages", stage_results)?;
+        Ok(result)
+    })?;
+    globals.set("pipeline", pipeline)?;
+
+    Ok(())
+}
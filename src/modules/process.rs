@@ -1,8 +1,15 @@
+#[cfg(unix)]
+use crate::modules::pty::{spawn_command_in_pty, spawn_in_pty, PtyProcess};
+use crate::modules::encoding::{ChunkDecoder, LineReader, TextEncoding};
+use crate::modules::interrupt::{kill_child, register_child};
+use crate::modules::jobserver::set_jobserver_env;
+use crate::modules::text::strip_ansi_bytes;
+use crate::modules::usage::{record_usage, wait_with_usage, ProcessUsage};
 use mlua::{Error, Lua, Value};
-use std::collections::HashMap;
 use std::process::Stdio;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio::process::Command;
+use tokio::sync::mpsc;
 
 pub fn setup_globals_process(
     lua: Lua,
@@ -15,68 +22,65 @@ pub fn setup_globals_process(
     // ================ Выполнение команд с callback ================
 
     // Выполняет команду с построчной обработкой вывода
-    // task_run("cargo build", function(line) print(line) end)
-    // Возвращает: boolean (true если команда завершилась успешно)
-    let task_run = lua.create_async_function(|_, (command, callback): (String, mlua::Function)| async move {
-        let mut child = create_command(&command).spawn();
-
-        match child {
-            Ok(mut process) => {
-                let stdout = process.stdout.take().unwrap();
-                let stderr = process.stderr.take().unwrap();
-
-                let stdout_reader = BufReader::new(stdout);
-                let stderr_reader = BufReader::new(stderr);
-
-                let mut stdout_lines = stdout_reader.lines();
-                let mut stderr_lines = stderr_reader.lines();
-
-                loop {
-                    tokio::select! {
-                        line = stdout_lines.next_line() => {
-                            match line {
-                                Ok(Some(line)) => {
-                                    callback.call_async::<mlua::Value>(line).await
-                                        .map_err(|e| mlua::Error::external(format!("Callback error: {}", e)))?;
-                                },
-                                Ok(None) => break,
-                                Err(e) => {
-                                    let error_line = format!("[UTF-8 ERROR] {}", e);
-                                    callback.call_async::<mlua::Value>(error_line).await
-                                        .map_err(|e| mlua::Error::external(format!("Callback error: {}", e)))?;
-                                },
-                            }
-                        },
-                        line = stderr_lines.next_line() => {
-                            match line {
-                                Ok(Some(line)) => {
-                                    let error_line = format!("{}", line);
-                                    callback.call_async::<mlua::Value>(error_line).await
-                                        .map_err(|e| mlua::Error::external(format!("Callback error: {}", e)))?;
-                                },
-                                Ok(None) => {},
-                                Err(e) => {
-                                    let error_line = format!("[STDERR UTF-8 ERROR] {}", e);
-                                    callback.call_async::<mlua::Value>(error_line).await
-                                        .map_err(|e| mlua::Error::external(format!("Callback error: {}", e)))?;
-                                },
-                            }
-                        },
+    // task_run("cargo build", function(line, stream)
+    //   if stream == "stderr" then print_error(line) else print(line) end
+    // end)
+    // local ok, exit_code = task_run("cargo test", print)
+    // Callback получает строку и источник: "stdout" или "stderr"
+    // Если callback вернул false или "abort", процесс завершается, а задача считается проваленной
+    // (вторым возвращаемым значением можно указать причину: return false, "compiler error")
+    // Опции: compat (boolean) - старое поведение: callback получает только строку,
+    //        а в конце вызывается со строкой "[EXIT] Process finished with code: N"
+    //        pty (boolean) - запуск в псевдотерминале (только Unix), весь вывод приходит как "stdout"
+    //        strip_ansi (boolean) - удалять ANSI escape-последовательности из строк
+    //        encoding (string) - кодировка вывода: "utf-8" (по умолчанию), "cp866", "cp1251",
+    //        "utf-16le" и др., "raw" - строки передаются как есть, без декодирования
+    // Возвращает: boolean (true если команда завершилась успешно), number (код выхода),
+    //             string | nil (причина прерывания callback'ом),
+    //             table | nil (потраченные ресурсы {wall_time, user_time, system_time, max_rss})
+    let task_run = lua.create_async_function(|lua, (command, callback, options): (String, mlua::Function, Option<mlua::Table>)| async move {
+        let options = TaskOptions::from_table(options.as_ref())?;
+
+        let start_time = std::time::Instant::now();
+        let (mut process, mut lines) = spawn_with_line_output(&command, options.pty, options.encoding)
+            .map_err(|e| mlua::Error::external(format!("Failed to spawn process: {}", e)))?;
+
+        while let Some((line, stream)) = lines.recv().await {
+            let text = output_line(&lua, &line, options.strip_ansi)?;
+            let line = text.to_string_lossy();
+
+            let abort_reason = if options.compat {
+                call_output_callback(&callback, text, &line, "Callback error").await?
+            } else {
+                call_output_callback(&callback, (text, stream), &line, "Callback error").await?
+            };
+
+            if let Some(reason) = abort_reason {
+                kill_child(&mut process, options.pty && cfg!(unix));
+                let usage = match wait_with_usage(&mut process, start_time).await {
+                    Ok((_, usage)) => {
+                        record_usage(&lua, &command, usage);
+                        Some(usage.to_table(&lua)?)
                     }
-                }
+                    Err(_) => None,
+                };
+                return Ok((false, -1, Some(reason), usage));
+            }
+        }
 
-                let status = process.wait().await;
-                match status {
-                    Ok(exit_status) => {
-                        let exit_message = format!("[EXIT] Process finished with code: {}", exit_status.code().unwrap_or(-1));
-                        callback.call_async::<mlua::Value>(exit_message).await
-                            .map_err(|e| mlua::Error::external(format!("Callback error: {}", e)))?;
-                        Ok(exit_status.success())
-                    },
-                    Err(e) => Err(mlua::Error::external(format!("Process wait error: {}", e))),
+        let status = wait_with_usage(&mut process, start_time).await;
+        match status {
+            Ok((exit_status, usage)) => {
+                record_usage(&lua, &command, usage);
+                let exit_code = exit_status.code().unwrap_or(-1);
+                if options.compat {
+                    let exit_message = format!("[EXIT] Process finished with code: {}", exit_code);
+                    callback.call_async::<mlua::Value>(exit_message).await
+                        .map_err(|e| mlua::Error::external(format!("Callback error: {}", e)))?;
                 }
+                Ok((exit_status.success(), exit_code, None, Some(usage.to_table(&lua)?)))
             },
-            Err(e) => Err(mlua::Error::external(format!("Failed to spawn process: {}", e))),
+            Err(e) => Err(mlua::Error::external(format!("Process wait error: {}", e))),
         }
     })?;
     globals.set("task_run", task_run)?;
@@ -86,101 +90,50 @@ pub fn setup_globals_process(
     //   print("Lines: " .. progress.processed_lines .. ", Speed: " .. progress.lines_per_second)
     // end)
     // Callback получает таблицу: {line, processed_lines, elapsed_seconds, lines_per_second}
-    // Возвращает: boolean (успех выполнения)
-    let task_with_progress = lua.create_async_function(|lua, (command, progress_callback): (String, mlua::Function)| async move {
-        let mut child = create_command(&command).spawn();
-
-        match child {
-            Ok(mut process) => {
-                let stdout = process.stdout.take().unwrap();
-                let stderr = process.stderr.take().unwrap();
-
-                let stdout_reader = BufReader::new(stdout);
-                let stderr_reader = BufReader::new(stderr);
-
-                let mut stdout_lines = stdout_reader.lines();
-                let mut stderr_lines = stderr_reader.lines();
-
-                let start_time = std::time::Instant::now();
-                let mut processed_lines = 0;
-                let mut accumulated_output = Vec::new();
-
-                loop {
-                    tokio::select! {
-                        line = stdout_lines.next_line() => {
-                            match line {
-                                Ok(Some(line)) => {
-                                    processed_lines += 1;
-                                    accumulated_output.push(line.clone());
-
-                                    let elapsed = start_time.elapsed().as_secs();
-                                    let progress = lua.create_table()?;
-                                    progress.set("line", line)?;
-                                    progress.set("processed_lines", processed_lines)?;
-                                    progress.set("elapsed_seconds", elapsed)?;
-                                    progress.set("lines_per_second",
-                                        if elapsed > 0 { processed_lines as f64 / elapsed as f64 }
-                                        else { 0.0 })?;
-
-                                    progress_callback.call_async::<mlua::Value>(progress).await
-                                        .map_err(|e| mlua::Error::external(format!("Progress callback error: {}", e)))?;
-                                },
-                                Ok(None) => break,
-                                Err(e) => {
-                                    let error_progress = lua.create_table()?;
-                                    error_progress.set("error", format!("UTF-8 decode error: {}", e))?;
-                                    error_progress.set("processed_lines", processed_lines)?;
-
-                                    progress_callback.call_async::<mlua::Value>(error_progress).await
-                                        .map_err(|e| mlua::Error::external(format!("Progress callback error: {}", e)))?;
-                                },
-                            }
-                        },
-                        line = stderr_lines.next_line() => {
-                            match line {
-                                Ok(Some(line)) => {
-                                    let error_progress = lua.create_table()?;
-                                    error_progress.set("error", line)?;
-                                    error_progress.set("processed_lines", processed_lines)?;
-
-                                    progress_callback.call_async::<mlua::Value>(error_progress).await
-                                        .map_err(|e| mlua::Error::external(format!("Progress callback error: {}", e)))?;
-                                },
-                                Ok(None) => {},
-                                Err(e) => {
-                                    let error_progress = lua.create_table()?;
-                                    error_progress.set("error", format!("Stderr UTF-8 decode error: {}", e))?;
-                                    error_progress.set("processed_lines", processed_lines)?;
-
-                                    progress_callback.call_async::<mlua::Value>(error_progress).await
-                                        .map_err(|e| mlua::Error::external(format!("Progress callback error: {}", e)))?;
-                                },
-                            }
-                        },
-                    }
-                }
+    // Если callback вернул false или "abort", процесс завершается; итоговая таблица содержит
+    // aborted = true и abort_reason
+    // В итоговой таблице также есть usage - потраченные ресурсы, как у task_run
+    // Опции: те же, что у task_run (pty, strip_ansi, encoding)
+    // Возвращает: boolean (успех выполнения), number (код выхода), string | nil (причина прерывания)
+    let task_with_progress = lua.create_async_function(|lua, (command, progress_callback, options): (String, mlua::Function, Option<mlua::Table>)| async move {
+        let options = TaskOptions::from_table(options.as_ref())?;
+
+        let (process, mut lines) = spawn_with_line_output(&command, options.pty, options.encoding)
+            .map_err(|e| mlua::Error::external(format!("Failed to spawn process: {}", e)))?;
+
+        let start_time = std::time::Instant::now();
+        let mut processed_lines = 0;
+        let mut accumulated_output = Vec::new();
+        let mut abort_reason = None;
+
+        while let Some((line, stream)) = lines.recv().await {
+            let text = output_line(&lua, &line, options.strip_ansi)?;
+
+            if stream == "stderr" {
+                abort_reason = send_error_progress(&lua, &progress_callback, text, processed_lines).await?;
+            } else {
+                processed_lines += 1;
+                accumulated_output.push(text.as_bytes().to_vec());
+
+                let elapsed = start_time.elapsed().as_secs();
+                let progress = lua.create_table()?;
+                progress.set("line", text.clone())?;
+                progress.set("processed_lines", processed_lines)?;
+                progress.set("elapsed_seconds", elapsed)?;
+                progress.set("lines_per_second",
+                    if elapsed > 0 { processed_lines as f64 / elapsed as f64 }
+                    else { 0.0 })?;
+
+                abort_reason = call_output_callback(&progress_callback, progress, &text.to_string_lossy(), "Progress callback error").await?;
+            }
 
-                let status = process.wait().await;
-                match status {
-                    Ok(exit_status) => {
-                        let total_time = start_time.elapsed().as_secs();
-                        let final_result = lua.create_table()?;
-                        final_result.set("success", exit_status.success())?;
-                        final_result.set("exit_code", exit_status.code().unwrap_or(-1))?;
-                        final_result.set("total_lines", processed_lines)?;
-                        final_result.set("total_time", total_time)?;
-                        final_result.set("final_output", accumulated_output.join("\n"))?;
-
-                        progress_callback.call_async::<mlua::Value>(final_result).await
-                            .map_err(|e| mlua::Error::external(format!("Final callback error: {}", e)))?;
-
-                        Ok(exit_status.success())
-                    },
-                    Err(e) => Err(mlua::Error::external(format!("Process wait error: {}", e))),
-                }
-            },
-            Err(e) => Err(mlua::Error::external(format!("Failed to spawn process: {}", e))),
+            if abort_reason.is_some() {
+                break;
+            }
         }
+
+        finalize_process(&lua, &command, process, options.pty && cfg!(unix), &progress_callback,
+                         processed_lines, start_time, accumulated_output, abort_reason).await
     })?;
     globals.set("task_with_progress", task_with_progress)?;
 
@@ -190,10 +143,55 @@ pub fn setup_globals_process(
     // task_realtime("ping google.com", function(data)
     //   if data.line then print(data.line) end
     // end)
+    // task_realtime("cargo build", function(data) ... end, {pty = true, strip_ansi = true})
+    // task_realtime("ping -n 4 ya.ru", function(data) ... end, {encoding = "cp866"})
     // Callback получает данные немедленно по мере поступления
-    // Возвращает: boolean (успех выполнения)
-    let task_realtime = lua.create_async_function(|lua, (command, progress_callback): (String, mlua::Function)| async move {
-        let mut child = create_command(&command).spawn();
+    // Если callback вернул false или "abort", процесс завершается; итоговая таблица содержит
+    // aborted = true и abort_reason
+    // В итоговой таблице также есть usage - потраченные ресурсы, как у task_run
+    // Опции: pty (boolean) - запуск в псевдотерминале (только Unix): программы сохраняют
+    //        цвета и прогресс-бары, stderr приходит вместе с stdout в data.line
+    //        strip_ansi (boolean) - удалять ANSI escape-последовательности из строк
+    //        encoding (string) - кодировка вывода, как у task_run
+    // Возвращает: boolean (успех выполнения), number (код выхода), string | nil (причина прерывания)
+    let task_realtime = lua.create_async_function(|lua, (command, progress_callback, options): (String, mlua::Function, Option<mlua::Table>)| async move {
+        let options = TaskOptions::from_table(options.as_ref())?;
+
+        #[cfg(unix)]
+        if options.pty {
+            let PtyProcess { child, mut output } = spawn_in_pty(&command)
+                .map_err(|e| mlua::Error::external(format!("Failed to spawn process: {}", e)))?;
+
+            let start_time = std::time::Instant::now();
+            let mut processed_lines = 0;
+            let mut accumulated_output = Vec::new();
+            let mut buffer = Vec::new();
+            let mut decoder = ChunkDecoder::new(options.encoding);
+            let mut abort_reason = None;
+
+            while let Some(chunk) = output.recv().await {
+                buffer.extend_from_slice(&decoder.decode(&chunk, false));
+                abort_reason = process_buffer_lines(&lua, &mut buffer, &mut processed_lines,
+                                                    &mut accumulated_output, start_time, &progress_callback,
+                                                    options.strip_ansi).await?;
+                if abort_reason.is_some() {
+                    break;
+                }
+            }
+            if abort_reason.is_none() {
+                buffer.extend_from_slice(&decoder.decode(&[], true));
+                if !buffer.is_empty() {
+                    abort_reason = process_buffer_line(&lua, &mut buffer, &mut processed_lines,
+                                                       &mut accumulated_output, start_time, &progress_callback,
+                                                       options.strip_ansi).await?;
+                }
+            }
+
+            return finalize_process(&lua, &command, child, true, &progress_callback, processed_lines,
+                                    start_time, accumulated_output, abort_reason).await;
+        }
+
+        let child = create_command(&command).spawn().inspect(|child| register_child(child, false));
 
         match child {
             Ok(mut process) => {
@@ -205,8 +203,11 @@ pub fn setup_globals_process(
                 let mut accumulated_output = Vec::new();
                 let mut stdout_buffer = Vec::new();
                 let mut stderr_buffer = Vec::new();
+                let mut stdout_decoder = ChunkDecoder::new(options.encoding);
+                let mut stderr_decoder = ChunkDecoder::new(options.encoding);
                 let mut stdout_finished = false;
                 let mut stderr_finished = false;
+                let mut abort_reason = None;
 
                 loop {
                     let mut stdout_chunk = [0u8; 1024];
@@ -217,18 +218,22 @@ pub fn setup_globals_process(
                             match result {
                                 Ok(0) => {
                                     stdout_finished = true;
+                                    stdout_buffer.extend_from_slice(&stdout_decoder.decode(&[], true));
                                     if !stdout_buffer.is_empty() {
-                                        process_buffer_line(&lua, &mut stdout_buffer, &mut processed_lines,
-                                                          &mut accumulated_output, start_time, &progress_callback).await?;
+                                        abort_reason = process_buffer_line(&lua, &mut stdout_buffer, &mut processed_lines,
+                                                          &mut accumulated_output, start_time, &progress_callback,
+                                                          options.strip_ansi).await?;
                                     }
                                 },
                                 Ok(n) => {
-                                    stdout_buffer.extend_from_slice(&stdout_chunk[0..n]);
-                                    process_buffer_lines(&lua, &mut stdout_buffer, &mut processed_lines,
-                                                       &mut accumulated_output, start_time, &progress_callback).await?;
+                                    stdout_buffer.extend_from_slice(&stdout_decoder.decode(&stdout_chunk[0..n], false));
+                                    abort_reason = process_buffer_lines(&lua, &mut stdout_buffer, &mut processed_lines,
+                                                       &mut accumulated_output, start_time, &progress_callback,
+                                                          options.strip_ansi).await?;
                                 },
                                 Err(e) => {
-                                    send_error_progress(&lua, &progress_callback, format!("Stdout read error: {}", e), processed_lines).await?;
+                                    let message = lua.create_string(format!("Stdout read error: {}", e))?;
+                                    abort_reason = send_error_progress(&lua, &progress_callback, message, processed_lines).await?;
                                     stdout_finished = true;
                                 },
                             }
@@ -237,19 +242,21 @@ pub fn setup_globals_process(
                             match result {
                                 Ok(0) => {
                                     stderr_finished = true;
+                                    stderr_buffer.extend_from_slice(&stderr_decoder.decode(&[], true));
                                     if !stderr_buffer.is_empty() {
-                                        send_error_progress(&lua, &progress_callback,
-                                                          String::from_utf8_lossy(&stderr_buffer).trim().to_string(),
-                                                          processed_lines).await?;
+                                        let line = output_line(&lua, stderr_buffer.trim_ascii(), options.strip_ansi)?;
+                                        abort_reason = send_error_progress(&lua, &progress_callback, line, processed_lines).await?;
                                         stderr_buffer.clear();
                                     }
                                 },
                                 Ok(n) => {
-                                    stderr_buffer.extend_from_slice(&stderr_chunk[0..n]);
-                                    process_stderr_lines(&lua, &mut stderr_buffer, &progress_callback, processed_lines).await?;
+                                    stderr_buffer.extend_from_slice(&stderr_decoder.decode(&stderr_chunk[0..n], false));
+                                    abort_reason = process_stderr_lines(&lua, &mut stderr_buffer, &progress_callback, processed_lines,
+                                                                        options.strip_ansi).await?;
                                 },
                                 Err(e) => {
-                                    send_error_progress(&lua, &progress_callback, format!("Stderr read error: {}", e), processed_lines).await?;
+                                    let message = lua.create_string(format!("Stderr read error: {}", e))?;
+                                    abort_reason = send_error_progress(&lua, &progress_callback, message, processed_lines).await?;
                                     stderr_finished = true;
                                 },
                             }
@@ -257,13 +264,13 @@ pub fn setup_globals_process(
                         else => break,
                     }
 
-                    if stdout_finished && stderr_finished {
+                    if (stdout_finished && stderr_finished) || abort_reason.is_some() {
                         break;
                     }
                 }
 
-                finalize_process(&lua, process, &progress_callback, processed_lines,
-                                 start_time, accumulated_output).await
+                finalize_process(&lua, &command, process, false, &progress_callback, processed_lines,
+                                 start_time, accumulated_output, abort_reason).await
             },
             Err(e) => Err(mlua::Error::external(format!("Failed to spawn process: {}", e))),
         }
@@ -339,116 +346,265 @@ pub fn setup_globals_process(
     let is_unix = lua.create_function(|_, ()| Ok(cfg!(unix)))?;
     globals.set("is_unix", is_unix)?;
 
-    // ================ Cargo команды ================
-
-    // Выполняет cargo build с опциональным release режимом
-    // local result = cargo_build(true) -- release build
-    // Возвращает: таблица {exit_code, stdout, stderr, success}
-    let cargo_build = lua.create_async_function(|_, release_mode: Option<bool>| async move {
-        let command = if release_mode.unwrap_or(false) {
-            "cargo build --release"
-        } else {
-            "cargo build"
-        };
-
-        let output = create_command(command).output().await;
-
-        match output {
-            Ok(result) => {
-                let stdout = String::from_utf8_lossy(&result.stdout).to_string();
-                let stderr = String::from_utf8_lossy(&result.stderr).to_string();
-
-                let mut response = HashMap::new();
-                response.insert("exit_code".to_string(), result.status.code().unwrap_or(-1));
-                response.insert("stdout".to_string(), stdout.parse().unwrap());
-                response.insert("stderr".to_string(), stderr.parse().unwrap());
-                response.insert("success".to_string(), if result.status.success() { 1 } else { 0 });
-
-                Ok(response)
-            }
-            Err(e) => Err(mlua::Error::external(format!("Cargo build failed: {}", e))),
-        }
-    })?;
-    globals.set("cargo_build", cargo_build)?;
-
     Ok(())
 }
 
 // ================ Вспомогательные функции ================
 
-fn create_command(command: &str) -> Command {
+pub(crate) fn create_command(command: &str) -> Command {
     if cfg!(target_os = "windows") {
         let mut cmd = Command::new("cmd");
         cmd.args(["/C", command])
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
+        set_jobserver_env(&mut cmd, false);
         cmd
     } else {
         let mut cmd = Command::new("sh");
         cmd.args(["-c", command])
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
+        set_jobserver_env(&mut cmd, false);
         cmd
     }
 }
 
+// Создает команду из списка аргументов без участия shell
+// {"git", "log", "--oneline"} -> git log --oneline
+pub(crate) fn create_command_from_args(args: mlua::Table) -> mlua::Result<Command> {
+    let args: Vec<String> = args.sequence_values().collect::<mlua::Result<_>>()?;
+    let (program, rest) = args
+        .split_first()
+        .ok_or_else(|| mlua::Error::external("Empty command"))?;
+    let mut cmd = Command::new(program);
+    cmd.args(rest);
+    set_jobserver_env(&mut cmd, false);
+    Ok(cmd)
+}
+
+// Общие опции команд task_*
+#[derive(Default)]
+pub(crate) struct TaskOptions {
+    pub(crate) compat: bool,
+    pub(crate) pty: bool,
+    pub(crate) strip_ansi: bool,
+    pub(crate) encoding: TextEncoding,
+}
+
+impl TaskOptions {
+    pub(crate) fn from_table(options: Option<&mlua::Table>) -> mlua::Result<Self> {
+        let Some(options) = options else {
+            return Ok(Self::default());
+        };
+        Ok(Self {
+            compat: options.get::<Option<bool>>("compat")?.unwrap_or(false),
+            pty: options.get::<Option<bool>>("pty")?.unwrap_or(false),
+            strip_ansi: options.get::<Option<bool>>("strip_ansi")?.unwrap_or(false),
+            encoding: TextEncoding::from_label(options.get::<Option<String>>("encoding")?.as_deref())?,
+        })
+    }
+}
+
+// Канал строк вывода процесса
+pub(crate) type LineReceiver = mpsc::Receiver<(Vec<u8>, &'static str)>;
+
+// Запускает команду и возвращает канал со строками вывода и их источником ("stdout"/"stderr")
+// Строки уже декодированы в UTF-8 (или остаются сырыми байтами для "raw")
+// Канал закрывается, когда процесс закрыл оба потока
+pub(crate) fn spawn_with_line_output(
+    command: &str,
+    pty: bool,
+    encoding: TextEncoding,
+) -> std::io::Result<(tokio::process::Child, LineReceiver)> {
+    spawn_command_with_line_output(create_command(command), pty, encoding)
+}
+
+// То же для заранее подготовленной команды (без shell, со своими аргументами и окружением)
+pub(crate) fn spawn_command_with_line_output(
+    mut command: Command,
+    pty: bool,
+    encoding: TextEncoding,
+) -> std::io::Result<(tokio::process::Child, LineReceiver)> {
+    let (sender, lines) = mpsc::channel(256);
+
+    #[cfg(unix)]
+    if pty {
+        let PtyProcess { child, mut output } = spawn_command_in_pty(command)?;
+        tokio::spawn(async move {
+            let mut decoder = ChunkDecoder::new(encoding);
+            let mut buffer = Vec::new();
+            while let Some(chunk) = output.recv().await {
+                buffer.extend_from_slice(&decoder.decode(&chunk, false));
+                // Одиночный \r (обновление прогресс-бара) тоже завершает строку, \r\n - одна граница
+                while let Some(pos) = buffer.iter().position(|&b| b == b'\n' || b == b'\r') {
+                    if buffer[pos] == b'\r' && pos + 1 == buffer.len() {
+                        break;
+                    }
+                    let skip = if buffer[pos] == b'\r' && buffer[pos + 1] == b'\n' { 2 } else { 1 };
+                    let mut line = buffer.drain(0..pos + skip).collect::<Vec<u8>>();
+                    line.truncate(pos);
+                    if sender.send((line, "stdout")).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            buffer.extend_from_slice(&decoder.decode(&[], true));
+            if buffer.last() == Some(&b'\r') {
+                buffer.pop();
+            }
+            if !buffer.is_empty() {
+                let _ = sender.send((buffer, "stdout")).await;
+            }
+        });
+        return Ok((child, lines));
+    }
+
+    let _ = pty;
+    let mut child = command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    register_child(&child, false);
+
+    let streams = [
+        (LineReader::new(Box::new(child.stdout.take().unwrap()) as Box<dyn AsyncRead + Send + Unpin>, encoding), "stdout"),
+        (LineReader::new(Box::new(child.stderr.take().unwrap()) as Box<dyn AsyncRead + Send + Unpin>, encoding), "stderr"),
+    ];
+    for (mut reader, stream) in streams {
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            while let Ok(Some(line)) = reader.next_line().await {
+                if sender.send((line, stream)).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    Ok((child, lines))
+}
+
+// Выполняет команду и собирает ее вывод, как Command::output, но с учетом процесса для Ctrl-C
+// и потраченных им ресурсов
+pub(crate) async fn command_output(command: &mut Command) -> std::io::Result<(std::process::Output, ProcessUsage)> {
+    let start_time = std::time::Instant::now();
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    register_child(&child, false);
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let mut stdout_pipe = child.stdout.take().unwrap();
+    let mut stderr_pipe = child.stderr.take().unwrap();
+    let (stdout_read, stderr_read) = tokio::join!(
+        stdout_pipe.read_to_end(&mut stdout),
+        stderr_pipe.read_to_end(&mut stderr)
+    );
+    stdout_read?;
+    stderr_read?;
+
+    let (status, usage) = wait_with_usage(&mut child, start_time).await?;
+    Ok((std::process::Output { status, stdout, stderr }, usage))
+}
+
+// Превращает строку вывода в Lua строку, при необходимости удаляя ANSI последовательности
+pub(crate) fn output_line(lua: &Lua, line: &[u8], strip_ansi: bool) -> mlua::Result<mlua::String> {
+    if strip_ansi {
+        lua.create_string(strip_ansi_bytes(line))
+    } else {
+        lua.create_string(line)
+    }
+}
+
+// Вызывает callback вывода и проверяет, не просит ли он прервать команду
+// Прерывание: callback вернул false или "abort"; второе возвращаемое значение - причина
+pub(crate) async fn call_output_callback(
+    callback: &mlua::Function,
+    args: impl mlua::IntoLuaMulti,
+    line: &str,
+    error_context: &str,
+) -> Result<Option<String>, mlua::Error> {
+    let values = callback.call_async::<mlua::MultiValue>(args).await
+        .map_err(|e| mlua::Error::external(format!("{}: {}", error_context, e)))?;
+
+    let mut values = values.into_iter();
+    let abort = match values.next() {
+        Some(Value::Boolean(false)) => true,
+        Some(Value::String(s)) => s.to_str().map(|s| s == "abort").unwrap_or(false),
+        _ => false,
+    };
+    if !abort {
+        return Ok(None);
+    }
+
+    let reason = match values.next() {
+        Some(Value::String(s)) => s.to_string_lossy(),
+        _ => format!("Aborted by callback on line: {}", line),
+    };
+    Ok(Some(reason))
+}
+
 async fn process_buffer_line(
     lua: &Lua,
     buffer: &mut Vec<u8>,
     processed_lines: &mut i32,
-    accumulated_output: &mut Vec<String>,
+    accumulated_output: &mut Vec<Vec<u8>>,
     start_time: std::time::Instant,
     progress_callback: &mlua::Function,
-) -> Result<(), mlua::Error> {
-    let line = String::from_utf8_lossy(buffer).trim().to_string();
-    if !line.is_empty() {
+    strip_ansi: bool,
+) -> Result<Option<String>, mlua::Error> {
+    let mut abort_reason = None;
+    let line = output_line(lua, buffer.trim_ascii(), strip_ansi)?;
+    if !line.as_bytes().is_empty() {
         *processed_lines += 1;
-        accumulated_output.push(line.clone());
+        accumulated_output.push(line.as_bytes().to_vec());
 
         let elapsed = start_time.elapsed().as_secs();
         let progress = lua.create_table()?;
-        progress.set("line", line)?;
+        progress.set("line", line.clone())?;
         progress.set("processed_lines", *processed_lines)?;
         progress.set("elapsed_seconds", elapsed)?;
         progress.set("lines_per_second",
                      if elapsed > 0 { *processed_lines as f64 / elapsed as f64 } else { 0.0 })?;
 
-        progress_callback.call_async::<mlua::Value>(progress).await
-            .map_err(|e| mlua::Error::external(format!("Progress callback error: {}", e)))?;
+        abort_reason = call_output_callback(progress_callback, progress, &line.to_string_lossy(), "Progress callback error").await?;
     }
     buffer.clear();
-    Ok(())
+    Ok(abort_reason)
 }
 
 async fn process_buffer_lines(
     lua: &Lua,
     buffer: &mut Vec<u8>,
     processed_lines: &mut i32,
-    accumulated_output: &mut Vec<String>,
+    accumulated_output: &mut Vec<Vec<u8>>,
     start_time: std::time::Instant,
     progress_callback: &mlua::Function,
-) -> Result<(), mlua::Error> {
+    strip_ansi: bool,
+) -> Result<Option<String>, mlua::Error> {
     while let Some(newline_pos) = buffer.iter().position(|&b| b == b'\n' || b == b'\r') {
         let line_bytes = buffer.drain(0..=newline_pos).collect::<Vec<u8>>();
-        let line = String::from_utf8_lossy(&line_bytes[0..line_bytes.len()-1]).trim().to_string();
+        let line = output_line(lua, line_bytes[0..line_bytes.len()-1].trim_ascii(), strip_ansi)?;
 
-        if !line.is_empty() {
+        if !line.as_bytes().is_empty() {
             *processed_lines += 1;
-            accumulated_output.push(line.clone());
+            accumulated_output.push(line.as_bytes().to_vec());
 
             let elapsed = start_time.elapsed().as_secs();
             let progress = lua.create_table()?;
-            progress.set("line", line)?;
+            progress.set("line", line.clone())?;
             progress.set("processed_lines", *processed_lines)?;
             progress.set("elapsed_seconds", elapsed)?;
             progress.set("lines_per_second",
                          if elapsed > 0 { *processed_lines as f64 / elapsed as f64 } else { 0.0 })?;
 
-            progress_callback.call_async::<mlua::Value>(progress).await
-                .map_err(|e| mlua::Error::external(format!("Progress callback error: {}", e)))?;
+            let abort_reason = call_output_callback(progress_callback, progress, &line.to_string_lossy(), "Progress callback error").await?;
+            if abort_reason.is_some() {
+                return Ok(abort_reason);
+            }
         }
     }
-    Ok(())
+    Ok(None)
 }
 
 async fn process_stderr_lines(
@@ -456,56 +612,73 @@ async fn process_stderr_lines(
     buffer: &mut Vec<u8>,
     progress_callback: &mlua::Function,
     processed_lines: i32,
-) -> Result<(), mlua::Error> {
+    strip_ansi: bool,
+) -> Result<Option<String>, mlua::Error> {
     while let Some(newline_pos) = buffer.iter().position(|&b| b == b'\n' || b == b'\r') {
         let line_bytes = buffer.drain(0..=newline_pos).collect::<Vec<u8>>();
-        let line = String::from_utf8_lossy(&line_bytes[0..line_bytes.len()-1]).trim().to_string();
+        let line = output_line(lua, line_bytes[0..line_bytes.len()-1].trim_ascii(), strip_ansi)?;
 
-        if !line.is_empty() {
-            send_error_progress(lua, progress_callback, line, processed_lines).await?;
+        if !line.as_bytes().is_empty() {
+            let abort_reason = send_error_progress(lua, progress_callback, line, processed_lines).await?;
+            if abort_reason.is_some() {
+                return Ok(abort_reason);
+            }
         }
     }
-    Ok(())
+    Ok(None)
 }
 
 async fn send_error_progress(
     lua: &Lua,
     progress_callback: &mlua::Function,
-    error_msg: String,
+    error_msg: mlua::String,
     processed_lines: i32,
-) -> Result<Value, Error> {
+) -> Result<Option<String>, Error> {
     let error_progress = lua.create_table()?;
-    error_progress.set("error", error_msg)?;
+    error_progress.set("error", error_msg.clone())?;
     error_progress.set("processed_lines", processed_lines)?;
 
-    progress_callback.call_async::<mlua::Value>(error_progress).await
-        .map_err(|e| mlua::Error::external(format!("Progress callback error: {}", e)))
+    call_output_callback(progress_callback, error_progress, &error_msg.to_string_lossy(), "Progress callback error").await
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn finalize_process(
     lua: &Lua,
+    command: &str,
     mut process: tokio::process::Child,
+    group: bool,
     progress_callback: &mlua::Function,
     processed_lines: i32,
     start_time: std::time::Instant,
-    accumulated_output: Vec<String>,
-) -> Result<bool, mlua::Error> {
-    let status = process.wait().await;
+    accumulated_output: Vec<Vec<u8>>,
+    abort_reason: Option<String>,
+) -> Result<(bool, i32, Option<String>), mlua::Error> {
+    if abort_reason.is_some() {
+        kill_child(&mut process, group);
+    }
+
+    let status = wait_with_usage(&mut process, start_time).await;
     match status {
-        Ok(exit_status) => {
+        Ok((exit_status, usage)) => {
+            record_usage(lua, command, usage);
+            let success = exit_status.success() && abort_reason.is_none();
+            let exit_code = exit_status.code().unwrap_or(-1);
             let total_time = start_time.elapsed().as_secs();
             let final_result = lua.create_table()?;
-            final_result.set("success", exit_status.success())?;
-            final_result.set("exit_code", exit_status.code().unwrap_or(-1))?;
+            final_result.set("success", success)?;
+            final_result.set("exit_code", exit_code)?;
             final_result.set("total_lines", processed_lines)?;
             final_result.set("total_time", total_time)?;
-            final_result.set("final_output", accumulated_output.join("\n"))?;
+            final_result.set("final_output", lua.create_string(accumulated_output.join(&b'\n'))?)?;
+            final_result.set("aborted", abort_reason.is_some())?;
+            final_result.set("abort_reason", abort_reason.clone())?;
+            final_result.set("usage", usage.to_table(lua)?)?;
 
             progress_callback.call_async::<mlua::Value>(final_result).await
                 .map_err(|e| mlua::Error::external(format!("Final callback error: {}", e)))?;
 
-            Ok(exit_status.success())
+            Ok((success, exit_code, abort_reason))
         },
         Err(e) => Err(mlua::Error::external(format!("Process wait error: {}", e))),
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,108 @@
+use std::fs::File;
+use std::io::Read;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::process::Stdio;
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc;
+
+use crate::modules::interrupt::register_child;
+use crate::modules::process::create_command;
+
+// Процесс, запущенный в псевдотерминале: stdout и stderr приходят одним потоком
+pub struct PtyProcess {
+    pub child: Child,
+    pub output: mpsc::Receiver<Vec<u8>>,
+}
+
+// Размер терминала по умолчанию, если собственный терминал определить не удалось
+const DEFAULT_COLUMNS: u16 = 120;
+const DEFAULT_ROWS: u16 = 40;
+
+// Запускает команду в псевдотерминале, чтобы она считала, что выводит в TTY
+// (цвета и прогресс-бары cargo, npm и т.д. сохраняются)
+pub fn spawn_in_pty(command: &str) -> std::io::Result<PtyProcess> {
+    spawn_command_in_pty(create_command(command))
+}
+
+// То же для заранее подготовленной команды
+pub fn spawn_command_in_pty(mut cmd: Command) -> std::io::Result<PtyProcess> {
+    let mut master = -1;
+    let mut slave = -1;
+    let size = terminal_size();
+
+    if unsafe { libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), &size) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let master = unsafe { OwnedFd::from_raw_fd(master) };
+    let slave = unsafe { OwnedFd::from_raw_fd(slave) };
+    // openpty не ставит FD_CLOEXEC: процессы, запущенные в это время из других потоков,
+    // унаследовали бы slave (и master не получил бы EOF) или master
+    set_cloexec(&master)?;
+    set_cloexec(&slave)?;
+
+    cmd.stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave))
+        .kill_on_drop(true);
+
+    // Новая сессия с PTY в качестве управляющего терминала
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    let child = cmd.spawn()?;
+    register_child(&child, true);
+    // Закрываем копии slave в родителе, иначе чтение master никогда не получит EOF
+    drop(cmd);
+
+    let (sender, output) = mpsc::channel(64);
+    let mut master = File::from(master);
+    std::thread::spawn(move || {
+        let mut buffer = [0u8; 4096];
+        loop {
+            match master.read(&mut buffer) {
+                // После завершения процесса Linux возвращает EIO вместо EOF
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if sender.blocking_send(buffer[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(PtyProcess { child, output })
+}
+
+fn terminal_size() -> libc::winsize {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    if !ok || size.ws_col == 0 || size.ws_row == 0 {
+        size.ws_col = DEFAULT_COLUMNS;
+        size.ws_row = DEFAULT_ROWS;
+    }
+    size
+}
+
+fn set_cloexec(fd: &OwnedFd) -> std::io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFD) };
+    if flags == -1 || unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, flags | libc::FD_CLOEXEC) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
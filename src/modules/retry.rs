@@ -0,0 +1,308 @@
+use crate::modules::process::{call_output_callback, output_line, spawn_with_line_output, TaskOptions};
+use crate::modules::usage::{record_usage, wait_with_usage, ProcessUsage};
+use colored::Colorize;
+use mlua::Lua;
+use regex::bytes::Regex;
+use std::time::{Duration, Instant};
+
+// Журнал команд, выполненных через task_retry, для итогового отчета после скрипта
+#[derive(Default)]
+pub struct RetryReport {
+    entries: Vec<RetryEntry>,
+}
+
+struct RetryEntry {
+    command: String,
+    target: Option<String>,
+    success: bool,
+    retries: u32,
+    summary: String,
+}
+
+// Политика повторов: сколько раз и с какой паузой перезапускать команду
+struct RetryPolicy {
+    retries: u32,
+    delay: f64,
+    backoff: f64,
+    max_delay: f64,
+    exit_codes: Option<Vec<i32>>,
+    output: Option<Regex>,
+}
+
+impl RetryPolicy {
+    fn from_table(options: Option<&mlua::Table>) -> mlua::Result<Self> {
+        let mut policy = Self {
+            retries: 3,
+            delay: 1.0,
+            backoff: 2.0,
+            max_delay: 60.0,
+            exit_codes: None,
+            output: None,
+        };
+        let Some(options) = options else {
+            return Ok(policy);
+        };
+
+        policy.retries = options.get::<Option<u32>>("retries")?.unwrap_or(policy.retries);
+        policy.delay = options.get::<Option<f64>>("delay")?.unwrap_or(policy.delay).max(0.0);
+        policy.backoff = options.get::<Option<f64>>("backoff")?.unwrap_or(policy.backoff).max(1.0);
+        policy.max_delay = options.get::<Option<f64>>("max_delay")?.unwrap_or(policy.max_delay).max(0.0);
+
+        if let Some(retry_on) = options.get::<Option<mlua::Table>>("retry_on")? {
+            policy.exit_codes = retry_on.get::<Option<Vec<i32>>>("exit_codes")?;
+            if let Some(pattern) = retry_on.get::<Option<String>>("output")? {
+                let regex = Regex::new(&pattern)
+                    .map_err(|e| mlua::Error::external(format!("Invalid retry_on.output regex: {}", e)))?;
+                policy.output = Some(regex);
+            }
+        }
+        Ok(policy)
+    }
+
+    // Без retry_on повторяется любой неуспешный запуск;
+    // с retry_on - только если совпал код выхода или вывод
+    fn should_retry(&self, exit_code: i32, output: &[u8]) -> bool {
+        if self.exit_codes.is_none() && self.output.is_none() {
+            return true;
+        }
+        let code_matches = self.exit_codes.as_ref().is_some_and(|codes| codes.contains(&exit_code));
+        let output_matches = self.output.as_ref().is_some_and(|regex| regex.is_match(output));
+        code_matches || output_matches
+    }
+
+    fn delay_before_retry(&self, retry: u32) -> f64 {
+        (self.delay * self.backoff.powi(retry as i32 - 1)).min(self.max_delay)
+    }
+}
+
+// Результат одной попытки
+struct Attempt {
+    success: bool,
+    exit_code: i32,
+    output: Vec<u8>,
+    duration: f64,
+    usage: Option<ProcessUsage>,
+    abort_reason: Option<String>,
+}
+
+pub fn setup_globals_retry(
+    lua: Lua,
+    script_path: String,
+    target: &Option<String>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let globals = lua.globals();
+    lua.set_app_data(RetryReport::default());
+
+    // ================ Повтор нестабильных команд ================
+
+    // Выполняет команду, повторяя ее при неудаче
+    // local result = task_retry("cargo test", {retries = 2})
+    // local result = task_retry("npm publish", {
+    //     retries = 5, delay = 2, backoff = 2, max_delay = 30,
+    //     retry_on = {exit_codes = {1, 75}, output = "ETIMEDOUT|ECONNRESET"},
+    //     callback = function(line, stream, attempt) println(line) end,
+    // })
+    // if result.success then print_success(result.summary) end   -- "passed after 2 retries"
+    // Опции: retries (number, по умолчанию 3) - число повторов после первой попытки
+    //        delay (number, секунды, по умолчанию 1) - пауза перед первым повтором
+    //        backoff (number, по умолчанию 2) - множитель паузы для каждого следующего повтора
+    //        max_delay (number, секунды, по умолчанию 60) - максимальная пауза
+    //        retry_on (table) - повторять только при совпадении кода выхода (exit_codes)
+    //        или вывода с регулярным выражением (output); без retry_on повторяется любая неудача
+    //        callback (function(line, stream, attempt)) - обработка вывода, как у task_run
+    //        pty, strip_ansi, encoding - как у task_run
+    // Все вызовы попадают в итоговый отчет, который выводится после завершения скрипта
+    // Возвращает: таблица {success, exit_code, attempts, retries, summary, duration,
+    //             history = {{attempt, success, exit_code, output, duration, usage}, ...}}
+    let task_retry = lua.create_async_function(|lua, (command, options): (String, Option<mlua::Table>)| async move {
+        let task_options = TaskOptions::from_table(options.as_ref())?;
+        let policy = RetryPolicy::from_table(options.as_ref())?;
+        let callback = match &options {
+            Some(options) => options.get::<Option<mlua::Function>>("callback")?,
+            None => None,
+        };
+
+        let start_time = Instant::now();
+        let history = lua.create_table()?;
+        let mut attempt_number = 0;
+
+        let (summary, last) = loop {
+            attempt_number += 1;
+            let attempt = run_attempt(&lua, &command, &task_options, callback.as_ref(), attempt_number).await?;
+
+            let record = lua.create_table()?;
+            record.set("attempt", attempt_number)?;
+            record.set("success", attempt.success)?;
+            record.set("exit_code", attempt.exit_code)?;
+            record.set("output", lua.create_string(&attempt.output)?)?;
+            record.set("duration", attempt.duration)?;
+            if let Some(usage) = attempt.usage {
+                record.set("usage", usage.to_table(&lua)?)?;
+            }
+            history.push(record)?;
+
+            let retries = attempt_number - 1;
+            if attempt.success {
+                let summary = match retries {
+                    0 => "passed".to_string(),
+                    1 => "passed after 1 retry".to_string(),
+                    n => format!("passed after {} retries", n),
+                };
+                break (summary, attempt);
+            }
+
+            if let Some(reason) = &attempt.abort_reason {
+                let summary = format!("aborted: {}", reason);
+                break (summary, attempt);
+            }
+
+            if !policy.should_retry(attempt.exit_code, &attempt.output) {
+                let summary = format!("failed with exit code {} (not retryable)", attempt.exit_code);
+                break (summary, attempt);
+            }
+
+            if retries >= policy.retries {
+                let summary = format!("failed after {} attempts", attempt_number);
+                break (summary, attempt);
+            }
+
+            let delay = policy.delay_before_retry(retries + 1);
+            eprintln!(
+                "{}",
+                format!(
+                    "Attempt {}/{} of '{}' failed with exit code {}, retrying in {:.1}s...",
+                    attempt_number,
+                    policy.retries + 1,
+                    command,
+                    attempt.exit_code,
+                    delay
+                )
+                .yellow()
+            );
+            tokio::time::sleep(Duration::from_secs_f64(delay)).await;
+        };
+
+        let retries = attempt_number - 1;
+        let target = lua.globals().get::<Option<String>>("target").ok().flatten();
+        if let Some(mut report) = lua.app_data_mut::<RetryReport>() {
+            report.entries.push(RetryEntry {
+                command: command.clone(),
+                target,
+                success: last.success,
+                retries,
+                summary: summary.clone(),
+            });
+        }
+
+        let result = lua.create_table()?;
+        result.set("success", last.success)?;
+        result.set("exit_code", last.exit_code)?;
+        result.set("attempts", attempt_number)?;
+        result.set("retries", retries)?;
+        result.set("summary", summary)?;
+        result.set("duration", start_time.elapsed().as_secs_f64())?;
+        result.set("history", history)?;
+        Ok(result)
+    })?;
+    globals.set("task_retry", task_retry)?;
+
+    // Возвращает записи отчета о повторах для собственной отчетности целей
+    // for _, entry in ipairs(retry_report()) do
+    //     print(entry.command .. ": " .. entry.summary)
+    // end
+    // Возвращает: таблица {{command, target, success, retries, summary}, ...}
+    let retry_report = lua.create_function(|lua, ()| {
+        let entries = lua.create_table()?;
+        if let Some(report) = lua.app_data_ref::<RetryReport>() {
+            for entry in &report.entries {
+                let item = lua.create_table()?;
+                item.set("command", entry.command.clone())?;
+                item.set("target", entry.target.clone())?;
+                item.set("success", entry.success)?;
+                item.set("retries", entry.retries)?;
+                item.set("summary", entry.summary.clone())?;
+                entries.push(item)?;
+            }
+        }
+        Ok(entries)
+    })?;
+    globals.set("retry_report", retry_report)?;
+
+    Ok(())
+}
+
+// ================ Вспомогательные функции ================
+
+async fn run_attempt(
+    lua: &Lua,
+    command: &str,
+    options: &TaskOptions,
+    callback: Option<&mlua::Function>,
+    attempt: u32,
+) -> mlua::Result<Attempt> {
+    let start_time = Instant::now();
+    let (mut process, mut lines) = spawn_with_line_output(command, options.pty, options.encoding)
+        .map_err(|e| mlua::Error::external(format!("Failed to spawn process: {}", e)))?;
+
+    let mut output = Vec::new();
+    while let Some((line, stream)) = lines.recv().await {
+        let text = output_line(lua, &line, options.strip_ansi)?;
+        if !output.is_empty() {
+            output.push(b'\n');
+        }
+        output.extend_from_slice(&text.as_bytes());
+
+        if let Some(callback) = callback {
+            let line = text.to_string_lossy();
+            if let Some(reason) = call_output_callback(callback, (text, stream, attempt), &line, "Callback error").await? {
+                let _ = process.kill().await;
+                return Ok(Attempt {
+                    success: false,
+                    exit_code: -1,
+                    output,
+                    duration: start_time.elapsed().as_secs_f64(),
+                    usage: None,
+                    abort_reason: Some(reason),
+                });
+            }
+        }
+    }
+
+    let (status, usage) = wait_with_usage(&mut process, start_time).await
+        .map_err(|e| mlua::Error::external(format!("Process wait error: {}", e)))?;
+    record_usage(lua, command, usage);
+    Ok(Attempt {
+        success: status.success(),
+        exit_code: status.code().unwrap_or(-1),
+        output,
+        duration: start_time.elapsed().as_secs_f64(),
+        usage: Some(usage),
+        abort_reason: None,
+    })
+}
+
+// Выводит итог по командам, которые понадобилось повторять или которые так и не прошли
+pub fn print_retry_summary(lua: &Lua) {
+    let Some(report) = lua.app_data_ref::<RetryReport>() else {
+        return;
+    };
+    let notable: Vec<&RetryEntry> = report.entries.iter().filter(|e| e.retries > 0 || !e.success).collect();
+    if notable.is_empty() {
+        return;
+    }
+
+    println!("{}", "Retry summary:".bold());
+    for entry in notable {
+        let name = match &entry.target {
+            Some(target) => format!("[{}] {}", target, entry.command),
+            None => entry.command.clone(),
+        };
+        let line = format!("  {}: {}", name, entry.summary);
+        if entry.success {
+            println!("{}", line.yellow());
+        } else {
+            println!("{}", line.red());
+        }
+    }
+}
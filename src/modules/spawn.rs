@@ -0,0 +1,413 @@
+use crate::modules::encoding::{LineReader, TextEncoding};
+use crate::modules::interrupt::register_child;
+#[cfg(unix)]
+use crate::modules::interrupt::is_running_child;
+use crate::modules::process::{create_command, create_command_from_args};
+use crate::modules::usage::{record_usage, wait_with_usage};
+use mlua::{Lua, UserData, UserDataMethods, Value};
+use std::collections::VecDeque;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWriteExt};
+use tokio::process::{Child, ChildStdin};
+use tokio::sync::{Mutex, Notify};
+
+// Сколько непрочитанных строк каждого потока хранится; более старые отбрасываются
+const OUTPUT_BUFFER_LINES: usize = 10_000;
+
+// Реестр всех процессов, запущенных через spawn(), для завершения при выходе из скрипта
+#[derive(Default)]
+pub struct SpawnRegistry {
+    children: Vec<Arc<SpawnedChild>>,
+}
+
+// Процесс вместе с данными для отчета о ресурсах
+struct SpawnedChild {
+    command: String,
+    start_time: Instant,
+    child: Mutex<Child>,
+}
+
+// Дескриптор фонового процесса, возвращаемый в Lua
+pub struct ProcessHandle {
+    pid: Option<u32>,
+    child: Arc<SpawnedChild>,
+    stdin: Arc<Mutex<Option<ChildStdin>>>,
+    stdout: Arc<OutputBuffer>,
+    stderr: Arc<OutputBuffer>,
+    kill_request: Arc<KillRequest>,
+}
+
+// Вывод процесса, который постоянно вычитывается в фоне: иначе заполненный канал (около 64 КБ)
+// остановил бы процесс, и wait() никогда бы не завершился
+#[derive(Default)]
+struct OutputBuffer {
+    // Непрочитанные строки и признак закрытия потока
+    state: std::sync::Mutex<(VecDeque<Vec<u8>>, bool)>,
+    changed: Notify,
+}
+
+// Сигнал для процесса, которого сейчас ожидает wait(): его передает kill()
+#[derive(Default)]
+struct KillRequest {
+    signal: std::sync::Mutex<Option<Option<i32>>>,
+    requested: Notify,
+}
+
+impl UserData for ProcessHandle {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        // Возвращает PID процесса
+        // local pid = server:pid()
+        // Возвращает: number | nil
+        methods.add_method("pid", |_, this, ()| Ok(this.pid));
+
+        // Записывает данные в stdin процесса
+        // server:write_stdin("quit\n")
+        // Возвращает: boolean (false если stdin уже закрыт)
+        methods.add_async_method("write_stdin", |_, this, data: mlua::String| {
+            let stdin = this.stdin.clone();
+            async move {
+                let mut guard = stdin.lock().await;
+                match guard.as_mut() {
+                    Some(pipe) => match pipe.write_all(&data.as_bytes()).await {
+                        Ok(_) => Ok(pipe.flush().await.is_ok()),
+                        Err(_) => Ok(false),
+                    },
+                    None => Ok(false),
+                }
+            }
+        });
+
+        // Закрывает stdin процесса (отправляет EOF)
+        // server:close_stdin()
+        methods.add_async_method("close_stdin", |_, this, ()| {
+            let stdin = this.stdin.clone();
+            async move {
+                stdin.lock().await.take();
+                Ok(())
+            }
+        });
+
+        // Читает следующую строку вывода процесса
+        // local line = server:read_line()           -- из stdout
+        // local err_line = server:read_line("stderr")
+        // Возвращает: string | nil (nil когда поток закрыт)
+        methods.add_async_method("read_line", |lua, this, stream: Option<String>| {
+            let buffer = match stream.as_deref().unwrap_or("stdout") {
+                "stdout" => Ok(this.stdout.clone()),
+                "stderr" => Ok(this.stderr.clone()),
+                other => Err(mlua::Error::external(format!("Unknown stream: {}", other))),
+            };
+            async move {
+                match buffer?.next_line().await {
+                    Some(line) => Ok(Some(lua.create_string(line)?)),
+                    None => Ok(None),
+                }
+            }
+        });
+
+        // Проверяет, работает ли процесс
+        // if server:is_running() then print("still alive") end
+        // Возвращает: boolean
+        methods.add_async_method("is_running", |_, this, ()| {
+            let child = this.child.clone();
+            async move {
+                // Блокировку держит wait(): процесс еще не завершился
+                match child.child.try_lock() {
+                    Ok(mut child) => Ok(is_alive(&mut child)),
+                    Err(_) => Ok(true),
+                }
+            }
+        });
+
+        // Ожидает завершения процесса
+        // local result = server:wait()
+        // Возвращает: таблица {success, exit_code}
+        methods.add_async_method("wait", |lua, this, ()| {
+            let child = this.child.clone();
+            let stdin = this.stdin.clone();
+            let kill_request = this.kill_request.clone();
+            let pid = this.pid;
+            async move {
+                // Закрываем stdin, чтобы процесс, читающий ввод, мог завершиться
+                stdin.lock().await.take();
+
+                let spawned = child;
+                let mut child = spawned.child.lock().await;
+                // Повторный wait(): ресурсы уже учтены при первом
+                let reaped = child.id().is_none();
+                let (status, usage) = loop {
+                    tokio::select! {
+                        result = wait_with_usage(&mut child, spawned.start_time) => {
+                            break result.map_err(|e| mlua::Error::external(format!("Process wait error: {}", e)))?;
+                        }
+                        // kill() из другой задачи, пока блокировка у нас
+                        _ = kill_request.requested.notified() => {
+                            if let Some(signal) = kill_request.signal.lock().unwrap().take() {
+                                signal_process(&mut child, pid, signal);
+                            }
+                        }
+                    }
+                };
+                if !reaped {
+                    record_usage(&lua, &spawned.command, usage);
+                }
+
+                let result = lua.create_table()?;
+                result.set("success", status.success())?;
+                result.set("exit_code", status.code().unwrap_or(-1))?;
+                Ok(result)
+            }
+        });
+
+        // Отправляет сигнал процессу
+        // server:kill()          -- SIGKILL
+        // server:kill("TERM")    -- SIGTERM (на Windows всегда принудительное завершение)
+        // Возвращает: boolean (true если сигнал отправлен)
+        methods.add_async_method("kill", |_, this, signal: Option<Value>| {
+            let child = this.child.clone();
+            let kill_request = this.kill_request.clone();
+            let pid = this.pid;
+            async move {
+                let signal = parse_signal(signal)?;
+                match child.child.try_lock() {
+                    Ok(mut child) => {
+                        if !is_alive(&mut child) {
+                            return Ok(false);
+                        }
+                        Ok(signal_process(&mut child, pid, signal))
+                    }
+                    // Процесс ожидает wait(): сигнал отправит он
+                    Err(_) => {
+                        *kill_request.signal.lock().unwrap() = Some(signal);
+                        kill_request.requested.notify_one();
+                        Ok(true)
+                    }
+                }
+            }
+        });
+    }
+}
+
+pub fn setup_globals_spawn(
+    lua: Lua,
+    script_path: String,
+    target: &Option<String>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let globals = lua.globals();
+
+    lua.set_app_data(SpawnRegistry::default());
+
+    // ================ Фоновые процессы ================
+
+    // Запускает процесс в фоне и сразу возвращает дескриптор
+    // local server = spawn("npm run dev", {cwd = "web", env = {PORT = "3000"}})
+    // local server = spawn({"python", "-m", "http.server"})  -- без shell
+    // print(server:read_line())
+    // server:kill("TERM")
+    // Опции: cwd (string), env (table), encoding (string) - кодировка вывода для read_line,
+    //        как у task_run
+    // Методы: pid(), write_stdin(data), close_stdin(), read_line([stream]),
+    //         is_running(), wait(), kill([signal])
+    // Вывод читается в фоне, поэтому процесс не останавливается, даже если read_line не вызывается;
+    // непрочитанными хранятся последние 10000 строк каждого потока
+    // Незавершенные процессы автоматически останавливаются при выходе из скрипта
+    // Возвращает: userdata (дескриптор процесса)
+    let spawn = lua.create_function(|lua, (command, options): (Value, Option<mlua::Table>)| {
+        let (command_line, mut cmd) = match command {
+            Value::String(s) => (s.to_str()?.to_string(), create_command(&s.to_str()?)),
+            Value::Table(args) => {
+                let words: Vec<String> = args.clone().sequence_values().collect::<mlua::Result<_>>()?;
+                (words.join(" "), create_command_from_args(args)?)
+            }
+            _ => return Err(mlua::Error::external("spawn: command must be a string or a table")),
+        };
+
+        let mut encoding = TextEncoding::default();
+        if let Some(options) = options {
+            encoding = TextEncoding::from_label(options.get::<Option<String>>("encoding")?.as_deref())?;
+            if let Some(cwd) = options.get::<Option<String>>("cwd")? {
+                cmd.current_dir(cwd);
+            }
+            if let Some(env) = options.get::<Option<mlua::Table>>("env")? {
+                for pair in env.pairs::<String, String>() {
+                    let (key, value) = pair?;
+                    cmd.env(key, value);
+                }
+            }
+        }
+
+        cmd.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        // Отдельная группа процессов, чтобы сигнал доходил и до потомков shell
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        let start_time = Instant::now();
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| mlua::Error::external(format!("Failed to spawn process: {}", e)))?;
+        register_child(&child, cfg!(unix));
+
+        let handle = ProcessHandle {
+            pid: child.id(),
+            stdin: Arc::new(Mutex::new(child.stdin.take())),
+            stdout: OutputBuffer::drain(child.stdout.take(), encoding),
+            stderr: OutputBuffer::drain(child.stderr.take(), encoding),
+            kill_request: Arc::new(KillRequest::default()),
+            child: Arc::new(SpawnedChild {
+                command: command_line,
+                start_time,
+                child: Mutex::new(child),
+            }),
+        };
+
+        if let Some(mut registry) = lua.app_data_mut::<SpawnRegistry>() {
+            registry.children.push(handle.child.clone());
+        }
+
+        Ok(handle)
+    })?;
+    globals.set("spawn", spawn)?;
+
+    Ok(())
+}
+
+impl OutputBuffer {
+    // Запускает фоновое чтение потока (None - поток уже закрыт)
+    fn drain<R: AsyncRead + Unpin + Send + 'static>(stream: Option<R>, encoding: TextEncoding) -> Arc<Self> {
+        let buffer = Arc::new(Self::default());
+        let Some(stream) = stream else {
+            buffer.close();
+            return buffer;
+        };
+        let writer = buffer.clone();
+        tokio::spawn(async move {
+            let mut lines = LineReader::new(stream, encoding);
+            while let Ok(Some(line)) = lines.next_line().await {
+                {
+                    let mut state = writer.state.lock().unwrap();
+                    if state.0.len() == OUTPUT_BUFFER_LINES {
+                        state.0.pop_front();
+                    }
+                    state.0.push_back(line);
+                }
+                writer.changed.notify_waiters();
+            }
+            writer.close();
+        });
+        buffer
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.changed.notify_waiters();
+    }
+
+    // Следующая строка; None - поток закрыт и все строки прочитаны
+    async fn next_line(&self) -> Option<Vec<u8>> {
+        loop {
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(line) = state.0.pop_front() {
+                    return Some(line);
+                }
+                if state.1 {
+                    return None;
+                }
+            }
+            changed.await;
+        }
+    }
+}
+
+// Останавливает все еще работающие процессы, запущенные через spawn()
+pub async fn terminate_spawned_processes(lua: &Lua, verbose: bool) {
+    let children = match lua.app_data_mut::<SpawnRegistry>() {
+        Some(mut registry) => std::mem::take(&mut registry.children),
+        None => return,
+    };
+
+    for spawned in children {
+        let mut child = spawned.child.lock().await;
+        // Уже дождались через wait()
+        if child.id().is_none() {
+            continue;
+        }
+        if is_alive(&mut child) {
+            if verbose {
+                println!("Terminating background process {}", child.id().unwrap_or(0));
+            }
+            let pid = child.id();
+            signal_process(&mut child, pid, None);
+        }
+        if let Ok((_, usage)) = wait_with_usage(&mut child, spawned.start_time).await {
+            record_usage(lua, &spawned.command, usage);
+        }
+    }
+}
+
+// Процесс еще работает; на Unix код возврата при этом не забирается,
+// чтобы wait() мог учесть потраченные процессом ресурсы
+fn is_alive(child: &mut Child) -> bool {
+    #[cfg(unix)]
+    {
+        child.id().is_some_and(is_running_child)
+    }
+
+    #[cfg(not(unix))]
+    {
+        matches!(child.try_wait(), Ok(None))
+    }
+}
+
+// Отправляет сигнал всей группе процессов (по умолчанию SIGKILL)
+fn signal_process(child: &mut Child, pid: Option<u32>, signal: Option<i32>) -> bool {
+    #[cfg(unix)]
+    if let Some(pid) = pid {
+        let signal = signal.unwrap_or(libc::SIGKILL);
+        return unsafe { libc::kill(-(pid as i32), signal) } == 0;
+    }
+
+    let _ = (pid, signal);
+    child.start_kill().is_ok()
+}
+
+// Преобразует имя или номер сигнала из Lua в номер сигнала Unix
+fn parse_signal(signal: Option<Value>) -> mlua::Result<Option<i32>> {
+    let signal = match signal {
+        None | Some(Value::Nil) => return Ok(None),
+        Some(Value::Integer(n)) => return Ok(Some(n as i32)),
+        Some(Value::String(s)) => s.to_str()?.to_uppercase(),
+        Some(_) => return Err(mlua::Error::external("kill: signal must be a string or a number")),
+    };
+
+    #[cfg(unix)]
+    {
+        let number = match signal.trim_start_matches("SIG") {
+            "KILL" => libc::SIGKILL,
+            "TERM" => libc::SIGTERM,
+            "INT" => libc::SIGINT,
+            "HUP" => libc::SIGHUP,
+            "QUIT" => libc::SIGQUIT,
+            "USR1" => libc::SIGUSR1,
+            "USR2" => libc::SIGUSR2,
+            other => return Err(mlua::Error::external(format!("Unknown signal: {}", other))),
+        };
+        Ok(Some(number))
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = signal;
+        Ok(None)
+    }
+}
@@ -0,0 +1,304 @@
+use colored::*;
+use mlua::{Lua, MetaMethod, MultiValue, UserData, UserDataMethods};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+// --keep-temp: временные файлы и папки не удаляются, а их пути печатаются в конце
+static KEEP_TEMP: AtomicBool = AtomicBool::new(false);
+
+// Реестр временных путей: область скрипта и области temp_scope
+pub struct TempRegistry {
+    // Пути, созданные вне temp_scope: удаляются в конце скрипта
+    script: Vec<Arc<TempEntry>>,
+    // Области temp_scope по корутине (указателю на нее), в которой выполняется их функция:
+    // параллельные задачи не попадают в чужую область
+    scopes: HashMap<usize, Vec<Arc<TempEntry>>>,
+}
+
+struct TempEntry {
+    path: PathBuf,
+    is_dir: bool,
+    kept: AtomicBool,
+}
+
+// Дескриптор временного файла или папки, возвращаемый в Lua
+pub struct TempHandle(Arc<TempEntry>);
+
+impl UserData for TempHandle {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        // Возвращает путь
+        // local dir = temp_dir(); task_run("tar xf archive.tar -C " .. dir:path())
+        // Возвращает: string
+        methods.add_method("path", |_, this, ()| Ok(this.0.path.to_string_lossy().into_owned()));
+
+        // Путь к элементу внутри временной папки
+        // local out = dir:join("out/result.txt")
+        // Возвращает: string
+        methods.add_method("join", |_, this, name: String| {
+            Ok(this.0.path.join(name).to_string_lossy().into_owned())
+        });
+
+        // Оставляет файл или папку после завершения скрипта
+        // local logs = temp_dir({prefix = "logs-"}); logs:keep()
+        // Возвращает: string (путь)
+        methods.add_method("keep", |_, this, ()| {
+            this.0.kept.store(true, Ordering::Relaxed);
+            Ok(this.0.path.to_string_lossy().into_owned())
+        });
+
+        // Удаляет файл или папку сразу, не дожидаясь конца скрипта
+        // dir:remove()
+        // Возвращает: boolean (true если удалено или уже не существует)
+        methods.add_method("remove", |_, this, ()| Ok(remove_entry(&this.0).is_ok()));
+
+        // Дескриптор можно использовать как строку: tostring(dir), dir .. "/file.txt"
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            Ok(this.0.path.to_string_lossy().into_owned())
+        });
+        methods.add_meta_function(MetaMethod::Concat, |_, (left, right): (mlua::Value, mlua::Value)| {
+            Ok(format!("{}{}", concat_part(&left)?, concat_part(&right)?))
+        });
+    }
+}
+
+pub fn setup_globals_temp(
+    lua: Lua,
+    script_path: String,
+    target: &Option<String>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let globals = lua.globals();
+
+    lua.set_app_data(TempRegistry {
+        script: Vec::new(),
+        scopes: HashMap::new(),
+    });
+
+    // ================ Временные файлы и папки ================
+
+    // Создает временную папку, которая удаляется при выходе из скрипта (и при ошибке или Ctrl-C)
+    // На unix папка доступна только владельцу (0700)
+    // local work = temp_dir()
+    // archive.extract("sdk.zip", work:path())
+    // copy_file(work .. "/sdk/bin/tool", "tools/tool")
+    // Опции: prefix (string, по умолчанию "luabuild-") - начало имени папки (без разделителей пути)
+    //        dir (string) - где создать папку (по умолчанию системная папка временных файлов)
+    // Методы: path(), join(name), keep(), remove()
+    // Возвращает: userdata (дескриптор, работает как строка с путем)
+    let temp_dir = lua.create_function(|lua, options: Option<mlua::Table>| {
+        let (parent, name) = temp_name(options.as_ref())?;
+        let path = create_unique(&parent, &name, |path| {
+            let mut builder = fs::DirBuilder::new();
+            // Только для владельца: в общей папке временных файлов ее не должны читать другие
+            #[cfg(unix)]
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+            builder.create(path)
+        })
+            .map_err(|e| mlua::Error::external(format!("Failed to create temporary directory: {}", e)))?;
+        Ok(register_temp(lua, path, true))
+    })?;
+    globals.set("temp_dir", temp_dir)?;
+
+    // Создает временный файл, который удаляется при выходе из скрипта (на unix с правами 0600)
+    // local list = temp_file({suffix = ".txt", content = table.concat(files, "\n")})
+    // task_run("tar cf out.tar -T " .. list:path())
+    // Опции: prefix (string, по умолчанию "luabuild-"), suffix (string) - окончание имени, например ".json"
+    //        dir (string) - где создать файл, content (string) - начальное содержимое
+    // Методы: path(), keep(), remove()
+    // Возвращает: userdata (дескриптор, работает как строка с путем)
+    let temp_file = lua.create_function(|lua, options: Option<mlua::Table>| {
+        let (parent, name) = temp_name(options.as_ref())?;
+        let content = match &options {
+            Some(options) => options.get::<Option<mlua::String>>("content")?,
+            None => None,
+        };
+        let path = create_unique(&parent, &name, |path| {
+            let mut open_options = File::options();
+            open_options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut open_options, 0o600);
+            let mut file = open_options.open(path)?;
+            if let Some(content) = &content {
+                file.write_all(&content.as_bytes())?;
+            }
+            Ok(())
+        })
+        .map_err(|e| mlua::Error::external(format!("Failed to create temporary file: {}", e)))?;
+        Ok(register_temp(lua, path, false))
+    })?;
+    globals.set("temp_file", temp_file)?;
+
+    // Выполняет функцию и удаляет временные файлы и папки, созданные внутри нее, сразу после ее завершения
+    // targets.package = function()
+    //     return temp_scope(function()
+    //         local stage = temp_dir()
+    //         copy_dir("build", stage:path())
+    //         return archive.create("dist/app.zip", stage:path())
+    //     end)
+    // end
+    // Область относится к корутине функции: пути, созданные в других корутинах (задачи parallel,
+    // callback'и task_run, другие temp_scope), в нее не попадают, а удаляются своей областью
+    // или в конце скрипта
+    // Возвращает: результаты функции (ошибка функции передается дальше после удаления)
+    let temp_scope = lua.create_async_function(|lua, callback: mlua::Function| async move {
+        // Своя корутина для функции, чтобы отличать ее temp_dir от вызовов из других задач
+        let thread = lua.create_thread(callback)?;
+        let key = thread.to_pointer() as usize;
+        if let Some(mut registry) = lua.app_data_mut::<TempRegistry>() {
+            registry.scopes.insert(key, Vec::new());
+        }
+        let result = match thread.into_async::<MultiValue>(()) {
+            Ok(thread) => thread.await,
+            Err(e) => Err(e),
+        };
+        let entries = match lua.app_data_mut::<TempRegistry>() {
+            Some(mut registry) => registry.scopes.remove(&key).unwrap_or_default(),
+            None => Vec::new(),
+        };
+        cleanup_entries(entries, false);
+        result
+    })?;
+    globals.set("temp_scope", temp_scope)?;
+
+    Ok(())
+}
+
+// Включает режим --keep-temp
+pub fn init_temp(keep: bool) {
+    KEEP_TEMP.store(keep, Ordering::Relaxed);
+}
+
+// Удаляет все оставшиеся временные файлы и папки скрипта
+pub fn cleanup_temp_files(lua: &Lua, verbose: bool) {
+    let entries: Vec<_> = match lua.app_data_mut::<TempRegistry>() {
+        Some(mut registry) => {
+            let scopes: Vec<_> = registry.scopes.drain().flat_map(|(_, entries)| entries).collect();
+            std::mem::take(&mut registry.script).into_iter().chain(scopes).collect()
+        }
+        None => return,
+    };
+    cleanup_entries(entries, verbose);
+}
+
+// ================ Вспомогательные функции ================
+
+fn cleanup_entries(entries: Vec<Arc<TempEntry>>, verbose: bool) {
+    let keep_all = KEEP_TEMP.load(Ordering::Relaxed);
+    // В обратном порядке: файлы внутри временной папки удаляются раньше нее
+    for entry in entries.iter().rev() {
+        if keep_all {
+            if entry.path.exists() {
+                println!("{}", format!("Keeping temporary {}", entry.path.display()).dimmed());
+            }
+            continue;
+        }
+        // Оставленные через keep() и уже удаленные через remove()
+        if entry.kept.load(Ordering::Relaxed) || fs::symlink_metadata(&entry.path).is_err() {
+            continue;
+        }
+        match remove_entry(entry) {
+            Ok(()) if verbose => println!("{}", format!("Removed temporary {}", entry.path.display()).dimmed()),
+            Ok(()) => {}
+            // Например, файл еще открыт другим процессом на Windows
+            Err(e) => eprintln!(
+                "{}",
+                format!("Failed to remove temporary {}: {}", entry.path.display(), e).yellow()
+            ),
+        }
+    }
+}
+
+fn remove_entry(entry: &TempEntry) -> io::Result<()> {
+    let result = if entry.is_dir {
+        fs::remove_dir_all(&entry.path)
+    } else {
+        fs::remove_file(&entry.path)
+    };
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn register_temp(lua: &Lua, path: PathBuf, is_dir: bool) -> TempHandle {
+    let entry = Arc::new(TempEntry {
+        path,
+        is_dir,
+        kept: AtomicBool::new(false),
+    });
+    let key = lua.current_thread().to_pointer() as usize;
+    if let Some(mut registry) = lua.app_data_mut::<TempRegistry>() {
+        let registry = &mut *registry;
+        registry.scopes.get_mut(&key).unwrap_or(&mut registry.script).push(entry.clone());
+    }
+    TempHandle(entry)
+}
+
+// Папка и шаблон имени (prefix, suffix) из опций
+fn temp_name(options: Option<&mlua::Table>) -> mlua::Result<(PathBuf, (String, String))> {
+    let (prefix, suffix, dir) = match options {
+        Some(options) => (
+            options.get::<Option<String>>("prefix")?,
+            options.get::<Option<String>>("suffix")?,
+            options.get::<Option<String>>("dir")?,
+        ),
+        None => (None, None, None),
+    };
+    // prefix и suffix - части имени: с разделителем путь ушел бы из dir
+    for (option, value) in [("prefix", &prefix), ("suffix", &suffix)] {
+        if let Some(value) = value
+            && value.chars().any(std::path::is_separator)
+        {
+            return Err(mlua::Error::external(format!(
+                "Temporary {} must not contain path separators: '{}'",
+                option, value
+            )));
+        }
+    }
+    let parent = match dir {
+        Some(dir) => {
+            fs::create_dir_all(&dir).map_err(|e| mlua::Error::external(format!("{}: {}", dir, e)))?;
+            // Абсолютный путь: скрипт может сменить текущую папку до удаления
+            fs::canonicalize(&dir).map_err(|e| mlua::Error::external(format!("{}: {}", dir, e)))?
+        }
+        None => std::env::temp_dir(),
+    };
+    Ok((parent, (prefix.unwrap_or_else(|| "luabuild-".to_string()), suffix.unwrap_or_default())))
+}
+
+// Подбирает свободное имя: create должен завершаться ошибкой AlreadyExists, если путь занят
+fn create_unique(
+    parent: &Path,
+    (prefix, suffix): &(String, String),
+    create: impl Fn(&Path) -> io::Result<()>,
+) -> io::Result<PathBuf> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    loop {
+        let unique = format!(
+            "{:x}{:x}{:04x}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+            rand::random::<u16>()
+        );
+        let path = parent.join(format!("{}{}{}", prefix, unique, suffix));
+        match create(&path) {
+            Ok(()) => return Ok(path),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn concat_part(value: &mlua::Value) -> mlua::Result<String> {
+    match value {
+        mlua::Value::UserData(data) => Ok(data.borrow::<TempHandle>()?.0.path.to_string_lossy().into_owned()),
+        mlua::Value::String(s) => Ok(s.to_str()?.to_string()),
+        mlua::Value::Integer(n) => Ok(n.to_string()),
+        mlua::Value::Number(n) => Ok(n.to_string()),
+        _ => Err(mlua::Error::external("attempt to concatenate a temporary path with a non-string value")),
+    }
+}
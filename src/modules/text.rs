@@ -1,6 +1,25 @@
 use mlua::Lua;
 use regex::Regex;
 use std::collections::HashMap;
+use std::sync::LazyLock;
+
+// ESC-последовательности терминала: цвета, перемещение курсора, заголовок окна
+const ANSI_ESCAPE_PATTERN: &str = r"\x1b\[[0-?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)|\x1b[@-Z\\-_]";
+
+static ANSI_ESCAPE: LazyLock<Regex> = LazyLock::new(|| Regex::new(ANSI_ESCAPE_PATTERN).unwrap());
+
+static ANSI_ESCAPE_BYTES: LazyLock<regex::bytes::Regex> =
+    LazyLock::new(|| regex::bytes::Regex::new(&format!("(?-u){}", ANSI_ESCAPE_PATTERN)).unwrap());
+
+// Удаляет ANSI escape-последовательности из текста
+pub(crate) fn strip_ansi_codes(text: &str) -> String {
+    ANSI_ESCAPE.replace_all(text, "").into_owned()
+}
+
+// Удаляет ANSI escape-последовательности из произвольных байт (вывод в кодировке "raw")
+pub(crate) fn strip_ansi_bytes(bytes: &[u8]) -> Vec<u8> {
+    ANSI_ESCAPE_BYTES.replace_all(bytes, &b""[..]).into_owned()
+}
 
 pub fn setup_globals_text(
     lua: Lua,
@@ -307,5 +326,11 @@ pub fn setup_globals_text(
     })?;
     globals.set("replace_text", replace_text)?;
 
+    // Удаляет ANSI escape-последовательности (цвета, управление курсором)
+    // local clean = strip_ansi("\27[32mFinished\27[0m release")
+    // Возвращает: string ("Finished release")
+    let strip_ansi = lua.create_function(|_, text: String| Ok(strip_ansi_codes(&text)))?;
+    globals.set("strip_ansi", strip_ansi)?;
+
     Ok(())
 }
\ No newline at end of file
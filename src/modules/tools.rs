@@ -0,0 +1,344 @@
+use mlua::Lua;
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::process::Command;
+
+// Номер версии в выводе --version: 3.28.1, 1.79.0-nightly, 13.2
+static VERSION_PATTERN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\d+(?:\.\d+)+(?:[-+][0-9A-Za-z.-]+)?").unwrap());
+
+// Требование к инструменту: "cmake >= 3.20", "g++ >= 11", "node ^18", "go ~1.21", "ninja"
+static REQUIREMENT_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(\S+?)\s*(?:(>=|<=|==|=|>|<|\^|~)\s*v?(\d+(?:\.\d+)*)\s*)?$").unwrap());
+
+// Сколько ждать ответа на --version, прежде чем считать версию неизвестной
+const VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Кэш поиска инструментов на время выполнения скрипта
+// Сбрасывается, если скрипт изменил PATH через set_env
+#[derive(Default)]
+pub struct ToolCache {
+    path_env: Option<String>,
+    locations: HashMap<String, Option<PathBuf>>,
+    versions: HashMap<String, Option<String>>,
+}
+
+impl ToolCache {
+    fn refresh(&mut self) {
+        let path_env = std::env::var("PATH").ok();
+        if path_env != self.path_env {
+            self.path_env = path_env;
+            self.locations.clear();
+            self.versions.clear();
+        }
+    }
+}
+
+pub fn setup_globals_tools(
+    lua: Lua,
+    script_path: String,
+    target: &Option<String>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let globals = lua.globals();
+    lua.set_app_data(ToolCache::default());
+
+    // ================ Поиск инструментов ================
+
+    // Ищет исполняемый файл в PATH (на Windows с учетом PATHEXT: .exe, .cmd, .bat...)
+    // local gpp = which("g++")
+    // if not which("ninja") then print_error("ninja is not installed") end
+    // Возвращает: string | nil (полный путь к исполняемому файлу)
+    let which = lua.create_function(|lua, name: String| {
+        Ok(find_tool_cached(lua, &name).map(|path| path.to_string_lossy().to_string()))
+    })?;
+    globals.set("which", which)?;
+
+    // Определяет версию инструмента по выводу --version
+    // local version = tool_version("cmake")     -- "3.28.1"
+    // local rustc, line = tool_version("rustc") -- "1.79.0", "rustc 1.79.0 (129f3b996 2024-06-10)"
+    // Возвращает: string | nil (версия), string | nil (строка вывода, в которой найдена версия)
+    let tool_version = lua.create_async_function(|lua, name: String| async move {
+        match detect_version_cached(&lua, &name).await {
+            Some((version, line)) => Ok((Some(version), Some(line))),
+            None => Ok((None, None)),
+        }
+    })?;
+    globals.set("tool_version", tool_version)?;
+
+    // Проверяет наличие инструмента и его версию, завершая скрипт понятной ошибкой
+    // require_tool("cmake >= 3.20")
+    // require_tool("ninja")
+    // local gcc = require_tool("gcc >= 11")
+    // print(gcc.path, gcc.version)
+    // Операторы сравнения: >=, >, <=, <, = (==), ^ (та же старшая версия, как в cargo), ~ (та же минорная)
+    // Перед требуемой версией допускается "v": "node >= v18.17"
+    // Возвращает: таблица {name, path, version}
+    let require_tool = lua.create_async_function(|lua, requirement: String| async move {
+        let (name, constraint) = parse_requirement(&requirement)
+            .ok_or_else(|| mlua::Error::external(format!("Invalid tool requirement: '{}'", requirement)))?;
+
+        let path = find_tool_cached(&lua, &name).ok_or_else(|| {
+            mlua::Error::external(format!("Required tool '{}' was not found in PATH", name))
+        })?;
+
+        let version = detect_version_cached(&lua, &name).await.map(|(version, _)| version);
+
+        if let Some((operator, required)) = &constraint {
+            let Some(version) = &version else {
+                return Err(mlua::Error::external(format!(
+                    "Required tool '{}' found at {}, but its version could not be determined (need {} {})",
+                    name,
+                    path.display(),
+                    operator,
+                    required
+                )));
+            };
+            if !version_satisfies(version, operator, required) {
+                return Err(mlua::Error::external(format!(
+                    "Required tool '{}' {} found at {}, but {} {} is required",
+                    name,
+                    version,
+                    path.display(),
+                    operator,
+                    required
+                )));
+            }
+        }
+
+        let result = lua.create_table()?;
+        result.set("name", name)?;
+        result.set("path", path.to_string_lossy().to_string())?;
+        result.set("version", version)?;
+        Ok(result)
+    })?;
+    globals.set("require_tool", require_tool)?;
+
+    Ok(())
+}
+
+// ================ Вспомогательные функции ================
+
+fn find_tool_cached(lua: &Lua, name: &str) -> Option<PathBuf> {
+    if let Some(mut cache) = lua.app_data_mut::<ToolCache>() {
+        cache.refresh();
+        if let Some(location) = cache.locations.get(name) {
+            return location.clone();
+        }
+        let location = find_tool(name);
+        cache.locations.insert(name.to_string(), location.clone());
+        return location;
+    }
+    find_tool(name)
+}
+
+pub(crate) fn find_tool(name: &str) -> Option<PathBuf> {
+    let candidates = executable_names(name);
+
+    // Путь с разделителем проверяется как есть, без поиска в PATH
+    if name.contains('/') || name.contains('\\') {
+        return candidates.into_iter().map(PathBuf::from).find(|path| is_executable(path));
+    }
+
+    let path_env = std::env::var_os("PATH")?;
+    std::env::split_paths(&path_env)
+        .flat_map(|dir| candidates.iter().map(move |candidate| dir.join(candidate)))
+        .find(|path| is_executable(path))
+}
+
+#[cfg(windows)]
+fn executable_names(name: &str) -> Vec<String> {
+    let extensions = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+    let has_extension = extensions
+        .split(';')
+        .any(|ext| !ext.is_empty() && name.to_lowercase().ends_with(&ext.to_lowercase()));
+
+    let mut names = Vec::new();
+    if has_extension {
+        names.push(name.to_string());
+    }
+    names.extend(extensions.split(';').filter(|ext| !ext.is_empty()).map(|ext| format!("{}{}", name, ext)));
+    names
+}
+
+#[cfg(not(windows))]
+fn executable_names(name: &str) -> Vec<String> {
+    vec![name.to_string()]
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+async fn detect_version_cached(lua: &Lua, name: &str) -> Option<(String, String)> {
+    let cached = lua.app_data_mut::<ToolCache>().and_then(|mut cache| {
+        cache.refresh();
+        cache.versions.get(name).cloned()
+    });
+    if let Some(line) = cached {
+        return line.map(|line| (extract_version(&line).unwrap_or_default(), line));
+    }
+
+    let path = find_tool_cached(lua, name);
+    let line = match path {
+        Some(path) => detect_version_line(name, &path).await,
+        None => None,
+    };
+    if let Some(mut cache) = lua.app_data_mut::<ToolCache>() {
+        cache.versions.insert(name.to_string(), line.clone());
+    }
+    line.map(|line| (extract_version(&line).unwrap_or_default(), line))
+}
+
+// Запускает инструмент с флагом версии и возвращает первую строку, содержащую номер версии
+async fn detect_version_line(name: &str, path: &Path) -> Option<String> {
+    let tool = Path::new(name)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let args: &[&str] = match tool.as_str() {
+        "go" => &["version"],
+        "java" | "javac" => &["-version"],
+        _ => &["--version"],
+    };
+
+    let output = Command::new(path)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(VERSION_TIMEOUT, output).await.ok()?.ok()?;
+
+    // Некоторые инструменты (java, старые gcc) выводят версию в stderr
+    let text = format!(
+        "{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    text.lines()
+        .map(str::trim)
+        .find(|line| VERSION_PATTERN.is_match(line))
+        .map(str::to_string)
+}
+
+fn extract_version(line: &str) -> Option<String> {
+    VERSION_PATTERN.find(line).map(|m| m.as_str().to_string())
+}
+
+// Разбирает требование "cmake >= 3.20" на имя и необязательные оператор и версию (без "v")
+fn parse_requirement(requirement: &str) -> Option<(String, Option<(String, String)>)> {
+    let captures = REQUIREMENT_PATTERN.captures(requirement)?;
+    let constraint = match (captures.get(2), captures.get(3)) {
+        (Some(operator), Some(required)) => Some((operator.as_str().to_string(), required.as_str().to_string())),
+        _ => None,
+    };
+    Some((captures[1].to_string(), constraint))
+}
+
+// Сравнивает версии по числовым компонентам; недостающие компоненты считаются нулями
+// ^ и ~ работают как в cargo: ^1.2 - от 1.2 до 2.0, ^0.3 - до 0.4, ~1.2.3 - от 1.2.3 до 1.3
+fn version_satisfies(version: &str, operator: &str, required: &str) -> bool {
+    let parse = |text: &str| -> Vec<u64> {
+        text.trim_start_matches('v')
+            .split(['-', '+'])
+            .next()
+            .unwrap_or("")
+            .split('.')
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
+    };
+    let mut actual = parse(version);
+    let mut expected = parse(required);
+    // Сколько первых компонентов должно совпасть для ^ и ~
+    let fixed = match operator {
+        "^" => expected.iter().position(|&part| part != 0).map_or(expected.len(), |index| index + 1),
+        "~" => expected.len().clamp(1, 2),
+        _ => 0,
+    };
+    let len = actual.len().max(expected.len());
+    actual.resize(len, 0);
+    expected.resize(len, 0);
+
+    let ordering = actual.cmp(&expected);
+    match operator {
+        ">=" => ordering.is_ge(),
+        ">" => ordering.is_gt(),
+        "<=" => ordering.is_le(),
+        "<" => ordering.is_lt(),
+        "^" | "~" => ordering.is_ge() && actual[..fixed] == expected[..fixed],
+        _ => ordering.is_eq(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_satisfies_compares_numeric_components() {
+        assert!(version_satisfies("3.28.1", ">=", "3.20"));
+        assert!(version_satisfies("3.20", ">=", "3.20.0"));
+        assert!(!version_satisfies("3.9", ">=", "3.20"));
+        assert!(version_satisfies("11.4.0", ">", "11"));
+        assert!(!version_satisfies("11", ">", "11.0"));
+        assert!(version_satisfies("1.79.0-nightly", "<", "1.80"));
+        assert!(version_satisfies("2.0", "<=", "2"));
+        assert!(version_satisfies("1.2.0", "=", "1.2"));
+        assert!(version_satisfies("v18.17.0", "==", "18.17"));
+    }
+
+    #[test]
+    fn version_satisfies_caret_keeps_first_nonzero_component() {
+        assert!(version_satisfies("1.9.3", "^", "1.2"));
+        assert!(!version_satisfies("2.0.0", "^", "1.2"));
+        assert!(!version_satisfies("1.1.9", "^", "1.2"));
+        assert!(version_satisfies("0.3.7", "^", "0.3"));
+        assert!(!version_satisfies("0.4.0", "^", "0.3"));
+        assert!(version_satisfies("0.0.3", "^", "0.0.3"));
+        assert!(!version_satisfies("0.0.4", "^", "0.0.3"));
+    }
+
+    #[test]
+    fn version_satisfies_tilde_keeps_minor_version() {
+        assert!(version_satisfies("1.2.9", "~", "1.2.3"));
+        assert!(!version_satisfies("1.3.0", "~", "1.2.3"));
+        assert!(!version_satisfies("1.2.2", "~", "1.2.3"));
+        assert!(version_satisfies("1.21.5", "~", "1.21"));
+        assert!(version_satisfies("1.9", "~", "1"));
+        assert!(!version_satisfies("2.0", "~", "1"));
+    }
+
+    #[test]
+    fn parse_requirement_reads_name_operator_and_version() {
+        let constraint = |operator: &str, version: &str| Some((operator.to_string(), version.to_string()));
+        assert_eq!(parse_requirement("ninja"), Some(("ninja".to_string(), None)));
+        assert_eq!(parse_requirement("cmake >= 3.20"), Some(("cmake".to_string(), constraint(">=", "3.20"))));
+        assert_eq!(parse_requirement("g++>=11"), Some(("g++".to_string(), constraint(">=", "11"))));
+        assert_eq!(parse_requirement("node ^18"), Some(("node".to_string(), constraint("^", "18"))));
+        assert_eq!(parse_requirement("go ~1.21"), Some(("go".to_string(), constraint("~", "1.21"))));
+        assert_eq!(parse_requirement("node >= v1.2"), Some(("node".to_string(), constraint(">=", "1.2"))));
+        assert_eq!(parse_requirement(" python3 == 3.11 "), Some(("python3".to_string(), constraint("==", "3.11"))));
+    }
+
+    #[test]
+    fn parse_requirement_rejects_malformed_input() {
+        assert_eq!(parse_requirement("cmake >= "), None);
+        assert_eq!(parse_requirement("cmake >= abc"), None);
+        assert_eq!(parse_requirement("cmake 3.20"), None);
+        assert_eq!(parse_requirement(""), None);
+    }
+}
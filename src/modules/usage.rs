@@ -0,0 +1,251 @@
+use colored::Colorize;
+use mlua::Lua;
+use std::process::ExitStatus;
+use std::time::Instant;
+use tokio::process::Child;
+
+// Сколько самых долгих команд выводится в сводке без --verbose
+const SUMMARY_TOP: usize = 5;
+
+// Журнал ресурсов, потраченных всеми запущенными скриптом процессами, для итогового отчета
+#[derive(Default)]
+pub struct UsageReport {
+    entries: Vec<UsageEntry>,
+}
+
+struct UsageEntry {
+    command: String,
+    target: Option<String>,
+    usage: ProcessUsage,
+}
+
+// Ресурсы, потраченные процессом вместе с его потомками
+// CPU время и пиковая память известны только на Linux
+#[derive(Clone, Copy, Default)]
+pub(crate) struct ProcessUsage {
+    pub(crate) wall_time: f64,
+    pub(crate) user_time: Option<f64>,
+    pub(crate) system_time: Option<f64>,
+    // Байты
+    pub(crate) max_rss: Option<u64>,
+}
+
+impl ProcessUsage {
+    // Таблица для результата: {wall_time, user_time, system_time, max_rss}
+    pub(crate) fn to_table(self, lua: &Lua) -> mlua::Result<mlua::Table> {
+        let table = lua.create_table()?;
+        table.set("wall_time", self.wall_time)?;
+        table.set("user_time", self.user_time)?;
+        table.set("system_time", self.system_time)?;
+        table.set("max_rss", self.max_rss)?;
+        Ok(table)
+    }
+}
+
+pub fn setup_globals_usage(
+    lua: Lua,
+    script_path: String,
+    target: &Option<String>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let globals = lua.globals();
+    lua.set_app_data(UsageReport::default());
+
+    // ================ Статистика ресурсов ================
+
+    // Возвращает ресурсы, потраченные каждой командой task_run, task_with_progress, task_realtime,
+    // cargo.*, task_retry, spawn, pipeline (по процессу на стадию), parallel и cc (компиляция и линковка)
+    // за время выполнения скрипта
+    // for _, entry in ipairs(usage_report()) do
+    //     print(entry.command, entry.wall_time, entry.max_rss)
+    // end
+    // user_time, system_time (секунды) и max_rss (байты) учитывают и дочерние процессы команды;
+    // на других системах известно только wall_time
+    // Возвращает: таблица {{command, target, wall_time, user_time, system_time, max_rss}, ...}
+    let usage_report = lua.create_function(|lua, ()| {
+        let entries = lua.create_table()?;
+        if let Some(report) = lua.app_data_ref::<UsageReport>() {
+            for entry in &report.entries {
+                let item = entry.usage.to_table(lua)?;
+                item.set("command", entry.command.clone())?;
+                item.set("target", entry.target.clone())?;
+                entries.push(item)?;
+            }
+        }
+        Ok(entries)
+    })?;
+    globals.set("usage_report", usage_report)?;
+
+    Ok(())
+}
+
+// Добавляет команду в итоговый отчет о ресурсах
+pub(crate) fn record_usage(lua: &Lua, command: &str, usage: ProcessUsage) {
+    let target = lua.globals().get::<Option<String>>("target").ok().flatten();
+    if let Some(mut report) = lua.app_data_mut::<UsageReport>() {
+        report.entries.push(UsageEntry {
+            command: command.to_string(),
+            target,
+            usage,
+        });
+    }
+}
+
+// Выводит самые затратные команды скрипта: SUMMARY_TOP самых долгих, с --verbose все
+pub fn print_usage_summary(lua: &Lua, verbose: bool) {
+    let Some(report) = lua.app_data_ref::<UsageReport>() else {
+        return;
+    };
+    if report.entries.is_empty() {
+        return;
+    }
+
+    let mut entries: Vec<&UsageEntry> = report.entries.iter().collect();
+    entries.sort_by(|a, b| b.usage.wall_time.total_cmp(&a.usage.wall_time));
+
+    println!("{}", "Resource usage:".bold());
+    let shown = if verbose { entries.len() } else { entries.len().min(SUMMARY_TOP) };
+    for entry in &entries[..shown] {
+        let name = match &entry.target {
+            Some(target) => format!("[{}] {}", target, entry.command),
+            None => entry.command.clone(),
+        };
+        println!("  {}: {}", name, format_usage(&entry.usage));
+    }
+    if shown < entries.len() {
+        println!("  {}", format!("... and {} more (--verbose shows all)", entries.len() - shown).dimmed());
+    }
+
+    let total = entries.iter().fold(ProcessUsage::default(), |total, entry| ProcessUsage {
+        wall_time: total.wall_time + entry.usage.wall_time,
+        user_time: sum(total.user_time, entry.usage.user_time),
+        system_time: sum(total.system_time, entry.usage.system_time),
+        max_rss: total.max_rss.max(entry.usage.max_rss),
+    });
+    println!("  {}", format!("Total ({} commands): {}", entries.len(), format_usage(&total)).dimmed());
+}
+
+// Ожидает завершения процесса и собирает потраченные им ресурсы
+// Код возврата забирает сам tokio (Child::wait), rusage читается раньше, пока процесс еще зомби
+pub(crate) async fn wait_with_usage(child: &mut Child, start_time: Instant) -> std::io::Result<(ExitStatus, ProcessUsage)> {
+    drop(child.stdin.take());
+    let usage = exit_usage(child, start_time).await?;
+    let status = child.wait().await?;
+    Ok((status, usage.unwrap_or_else(|| wall_usage(start_time))))
+}
+
+// ================ Вспомогательные функции ================
+
+// Дожидается завершения процесса, не забирая код возврата, и читает rusage зомби
+// через waitid с WNOWAIT: Linux заполняет пятый аргумент системного вызова, обертка libc его не передает
+#[cfg(target_os = "linux")]
+async fn exit_usage(child: &Child, start_time: Instant) -> std::io::Result<Option<ProcessUsage>> {
+    let Some(pid) = child.id() else {
+        // Процесс уже забран tokio, rusage недоступен
+        return Ok(None);
+    };
+    wait_for_exit(pid).await?;
+    let wall_time = start_time.elapsed().as_secs_f64();
+
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_waitid,
+            libc::P_PID,
+            pid as libc::id_t,
+            &mut info as *mut libc::siginfo_t,
+            libc::WEXITED | libc::WNOWAIT,
+            &mut rusage as *mut libc::rusage,
+        )
+    };
+    if result == -1 {
+        return Ok(None);
+    }
+
+    let seconds = |time: libc::timeval| time.tv_sec as f64 + time.tv_usec as f64 / 1_000_000.0;
+    Ok(Some(ProcessUsage {
+        wall_time,
+        user_time: Some(seconds(rusage.ru_utime)),
+        system_time: Some(seconds(rusage.ru_stime)),
+        // Килобайты
+        max_rss: Some(rusage.ru_maxrss as u64 * 1024),
+    }))
+}
+
+// На других системах rusage можно получить только вместе с кодом возврата, а его забирает tokio
+#[cfg(not(target_os = "linux"))]
+async fn exit_usage(_child: &Child, _start_time: Instant) -> std::io::Result<Option<ProcessUsage>> {
+    Ok(None)
+}
+
+fn wall_usage(start_time: Instant) -> ProcessUsage {
+    ProcessUsage {
+        wall_time: start_time.elapsed().as_secs_f64(),
+        ..ProcessUsage::default()
+    }
+}
+
+// Ждет завершения процесса, не забирая код возврата (ожидание можно отменить)
+#[cfg(target_os = "linux")]
+async fn wait_for_exit(pid: u32) -> std::io::Result<()> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use tokio::io::unix::AsyncFd;
+
+    // pidfd становится читаемым, когда процесс завершается
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        return poll_for_exit(pid).await;
+    }
+    let pidfd = AsyncFd::new(unsafe { OwnedFd::from_raw_fd(fd as i32) })?;
+    let _ = pidfd.readable().await?;
+    Ok(())
+}
+
+// Опрашивает состояние процесса через waitid с WNOWAIT, увеличивая паузу до 50 мс
+#[cfg(target_os = "linux")]
+async fn poll_for_exit(pid: u32) -> std::io::Result<()> {
+    use std::time::Duration;
+
+    let mut delay = Duration::from_millis(1);
+    loop {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let result = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+            )
+        };
+        if result == -1 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() != std::io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        } else if unsafe { info.si_pid() } != 0 {
+            return Ok(());
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(Duration::from_millis(50));
+    }
+}
+
+fn sum(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a + b),
+        (a, b) => a.or(b),
+    }
+}
+
+fn format_usage(usage: &ProcessUsage) -> String {
+    let mut parts = vec![format!("wall {:.2}s", usage.wall_time)];
+    if let (Some(user), Some(system)) = (usage.user_time, usage.system_time) {
+        parts.push(format!("user {:.2}s", user));
+        parts.push(format!("sys {:.2}s", system));
+    }
+    if let Some(max_rss) = usage.max_rss {
+        parts.push(format!("peak RSS {:.1} MB", max_rss as f64 / (1024.0 * 1024.0)));
+    }
+    parts.join(", ")
+}
@@ -465,4 +465,19 @@ pub fn setup_globals_utility(
     globals.set("json_stringify", json_stringify)?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+// ================ Вспомогательные функции ================
+
+// Принимает строку или массив строк
+pub(crate) fn string_list(value: Value, option: &str) -> mlua::Result<Vec<String>> {
+    match value {
+        Value::Nil => Ok(Vec::new()),
+        Value::String(s) => Ok(vec![s.to_str()?.to_string()]),
+        Value::Table(table) => table.sequence_values::<String>().collect(),
+        _ => Err(mlua::Error::external(format!(
+            "Option '{}' must be a string or a table of strings",
+            option
+        ))),
+    }
+}
@@ -0,0 +1,126 @@
+use ignore::WalkBuilder;
+use ignore::overrides::OverrideBuilder;
+use mlua::Table;
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+// Файл исключений проекта, дополняющий .gitignore и .ignore
+pub(crate) const SOLVER_IGNORE_FILE: &str = ".solverignore";
+
+// Читает опцию gitignore функций обхода папок
+pub(crate) fn gitignore_option(options: Option<&Table>) -> mlua::Result<bool> {
+    match options {
+        Some(options) => Ok(options.get::<Option<bool>>("gitignore")?.unwrap_or(false)),
+        None => Ok(false),
+    }
+}
+
+// Обход папки; при gitignore = true пропускаются пути из .gitignore, .ignore и .solverignore
+// (с отрицанием "!", вложенными файлами, .gitignore родительских папок и .git/info/exclude)
+// и сама папка .git. Скрытые файлы не пропускаются
+pub(crate) fn walk_builder(root: &Path, gitignore: bool) -> WalkBuilder {
+    let mut builder = WalkBuilder::new(root);
+    builder.standard_filters(false).follow_links(true);
+    if gitignore {
+        builder
+            .git_ignore(true)
+            .git_exclude(true)
+            .git_global(true)
+            .ignore(true)
+            .parents(true)
+            // .gitignore учитывается и в папках, которые не являются git репозиторием
+            .require_git(false)
+            .add_custom_ignore_filename(SOLVER_IGNORE_FILE);
+        if let Ok(overrides) = OverrideBuilder::new(root).add("!.git").and_then(|builder| builder.build()) {
+            builder.overrides(overrides);
+        }
+    }
+    builder
+}
+
+// Фильтр элементов одной папки по .gitignore, .ignore и .solverignore (None - опция выключена)
+// Для обхода вложенных папок создается для каждой из них: правила родительских папок учитываются
+pub(crate) struct IgnoreFilter(Option<HashSet<OsString>>);
+
+impl IgnoreFilter {
+    pub(crate) async fn new(path: &str, gitignore: bool) -> std::io::Result<Self> {
+        if !gitignore {
+            return Ok(Self(None));
+        }
+        let path = path.to_string();
+        let names = tokio::task::spawn_blocking(move || visible_names(Path::new(&path)))
+            .await
+            .map_err(std::io::Error::other)??;
+        Ok(Self(Some(names)))
+    }
+
+    pub(crate) fn is_ignored(&self, name: &OsStr) -> bool {
+        self.0.as_ref().is_some_and(|names| !names.contains(name))
+    }
+}
+
+// Имена элементов папки, которые не исключены файлами исключений
+fn visible_names(path: &Path) -> std::io::Result<HashSet<OsString>> {
+    let mut names = HashSet::new();
+    for entry in walk_builder(path, true).max_depth(Some(1)).build() {
+        match entry {
+            Ok(entry) if entry.depth() > 0 => {
+                names.insert(entry.file_name().to_os_string());
+            }
+            Ok(_) => {}
+            // Некорректные строки в файлах исключений пропускаются, как это делает git
+            Err(e) => {
+                if let Some(error) = e.io_error() {
+                    return Err(std::io::Error::new(error.kind(), e.to_string()));
+                }
+            }
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // Пустая папка для теста внутри системной папки временных файлов
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("solver-walk-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Относительные пути всех элементов обхода, отсортированные
+    fn walk(root: &Path, gitignore: bool) -> Vec<String> {
+        let mut paths: Vec<String> = walk_builder(root, gitignore)
+            .build()
+            .flatten()
+            .filter(|entry| entry.depth() > 0)
+            .map(|entry| entry.path().strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn gitignore_skips_git_dirs_but_not_other_hidden_files() {
+        let root = test_dir("git");
+        fs::create_dir_all(root.join(".git/objects")).unwrap();
+        fs::create_dir_all(root.join("vendor/lib/.git")).unwrap();
+        fs::write(root.join(".gitignore"), "build.log\n").unwrap();
+        fs::write(root.join(".env"), "").unwrap();
+        fs::write(root.join("build.log"), "").unwrap();
+
+        assert_eq!(walk(&root, true), [".env", ".gitignore", "vendor", "vendor/lib"]);
+        assert!(walk(&root, false).contains(&".git/objects".to_string()));
+
+        let names = visible_names(&root).unwrap();
+        assert!(!names.contains(OsStr::new(".git")));
+        assert!(names.contains(OsStr::new(".env")));
+        fs::remove_dir_all(root).unwrap();
+    }
+}